@@ -1,54 +1,207 @@
-use std::fs;
-use std::collections::HashSet;
-
-fn part1(input: &str) -> usize {
-    input.split("\n\n")
-        .map(|group| {
-            group
-                .chars()
-                .filter(|c| !c.is_whitespace())
-                .fold(HashSet::new(), |mut set, c| {
-                    set.insert(c);
-                    set
-                })
-                .len()
-        })
-        .sum()
+use std::{env, fs, fmt, str::FromStr};
+use adventofcode2020::ReadError;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct AnswerSet(u32);
+
+impl AnswerSet {
+    fn question_bit(question: char) -> Option<u32> {
+        if question.is_ascii_lowercase() {
+            Some(1 << (question as u8 - b'a'))
+        } else {
+            None
+        }
+    }
+
+    fn all() -> AnswerSet {
+        AnswerSet((1 << 26) - 1)
+    }
+
+    fn contains(&self, question: char) -> bool {
+        AnswerSet::question_bit(question)
+            .map(|bit| self.0 & bit != 0)
+            .unwrap_or(false)
+    }
+
+    fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    fn union(&self, other: &AnswerSet) -> AnswerSet {
+        AnswerSet(self.0 | other.0)
+    }
+
+    fn intersection(&self, other: &AnswerSet) -> AnswerSet {
+        AnswerSet(self.0 & other.0)
+    }
+
+    fn symmetric_difference(&self, other: &AnswerSet) -> AnswerSet {
+        AnswerSet(self.0 ^ other.0)
+    }
+
+    fn questions(&self) -> impl Iterator<Item = char> + '_ {
+        (b'a'..=b'z').map(|c| c as char).filter(move |&c| self.contains(c))
+    }
 }
 
-fn part2(input: &str) -> usize {
-    input.trim().split("\n\n")
-        .map(|group| {
-            group.split("\n")
-                .map(|row| {
-                    row
-                        .chars()
-                        .filter(|c| !c.is_whitespace())
-                        .fold(HashSet::new(), |mut set, c| {
-                            set.insert(c);
-                            set
-                        })
-                })
-                .fold(None, |acc, set| {
-                    match acc {
-                        None => Some(set),
-                        Some(acc) => {
-                            Some(acc.intersection(&set).cloned().collect())
-                        }
-                    }
-                })
-                .map(|set| set.len())
-                .unwrap_or(0)
+impl FromStr for AnswerSet {
+    type Err = char;
+
+    fn from_str(s: &str) -> Result<AnswerSet, char> {
+        s.chars().try_fold(AnswerSet::default(), |set, c| {
+            AnswerSet::question_bit(c)
+                .map(|bit| AnswerSet(set.0 | bit))
+                .ok_or(c)
         })
-        .sum()
+    }
 }
 
+impl fmt::Debug for AnswerSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{{}}}", self.questions().collect::<String>())
+    }
+}
+
+struct Group(Vec<AnswerSet>);
+
+impl Group {
+    fn union(&self) -> AnswerSet {
+        self.0.iter().fold(AnswerSet::default(), |acc, set| acc.union(set))
+    }
+
+    fn intersection(&self) -> AnswerSet {
+        if self.0.is_empty() {
+            return AnswerSet::default();
+        }
+        self.0.iter().fold(AnswerSet::all(), |acc, set| acc.intersection(set))
+    }
+
+    fn symmetric_difference(&self) -> AnswerSet {
+        self.0.iter().fold(AnswerSet::default(), |acc, set| acc.symmetric_difference(set))
+    }
+
+    fn frequencies(&self) -> [usize; 26] {
+        let mut freq = [0; 26];
+        for set in &self.0 {
+            for (i, count) in freq.iter_mut().enumerate() {
+                if set.0 & (1 << i) != 0 {
+                    *count += 1;
+                }
+            }
+        }
+        freq
+    }
+
+    fn frequency(&self, question: char) -> usize {
+        self.0.iter().filter(|set| set.contains(question)).count()
+    }
+
+    fn at_least(&self, k: usize) -> AnswerSet {
+        self.frequencies().iter().enumerate()
+            .filter(|(_, &count)| count >= k && count > 0)
+            .fold(AnswerSet::default(), |acc, (i, _)| AnswerSet(acc.0 | (1 << i)))
+    }
+}
+
+fn parse_groups(input: &str) -> Result<Vec<Group>, ReadError> {
+    let mut groups = vec![];
+    let mut members = vec![];
+    for (line_no, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            if !members.is_empty() {
+                groups.push(Group(std::mem::take(&mut members)));
+            }
+            continue;
+        }
+        let set = line.parse::<AnswerSet>()
+            .map_err(|_| ReadError::ParseError(line_no + 1, line.to_string()))?;
+        members.push(set);
+    }
+    if !members.is_empty() {
+        groups.push(Group(members));
+    }
+    Ok(groups)
+}
+
+#[derive(Debug, PartialEq)]
+enum Query {
+    Union,
+    Intersection,
+    SymmetricDifference,
+    AtLeast(usize),
+    Frequency(char),
+}
+
+impl Query {
+    fn evaluate_group(&self, group: &Group) -> usize {
+        match self {
+            Query::Union => group.union().len(),
+            Query::Intersection => group.intersection().len(),
+            Query::SymmetricDifference => group.symmetric_difference().len(),
+            Query::AtLeast(k) => group.at_least(*k).len(),
+            Query::Frequency(question) => group.frequency(*question),
+        }
+    }
+
+    fn evaluate(&self, groups: &[Group]) -> usize {
+        groups.iter().map(|group| self.evaluate_group(group)).sum()
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    // query syntax: `union`, `intersection`, `xor`, `atleast <k>`, `freq <question>`
+    fn from_str(s: &str) -> Result<Query, String> {
+        let mut parts = s.split_whitespace();
+        let query = match parts.next() {
+            Some("union") | Some("any") => Query::Union,
+            Some("intersection") | Some("all") => Query::Intersection,
+            Some("xor") | Some("symdiff") => Query::SymmetricDifference,
+            Some("atleast") => {
+                let k = parts.next()
+                    .and_then(|k| k.parse::<usize>().ok())
+                    .ok_or_else(|| format!("expected a count in query: {}", s))?;
+                Query::AtLeast(k)
+            },
+            Some("freq") => {
+                let question = parts.next()
+                    .filter(|q| q.len() == 1)
+                    .and_then(|q| q.chars().next())
+                    .filter(|q| q.is_ascii_lowercase())
+                    .ok_or_else(|| format!("expected a question in query: {}", s))?;
+                Query::Frequency(question)
+            },
+            _ => return Err(format!("unknown query: {}", s)),
+        };
+        if parts.next().is_some() {
+            return Err(format!("unexpected trailing input in query: {}", s));
+        }
+        Ok(query)
+    }
+}
+
+fn part1(groups: &[Group]) -> usize {
+    Query::Union.evaluate(groups)
+}
+
+fn part2(groups: &[Group]) -> usize {
+    Query::Intersection.evaluate(groups)
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = fs::read_to_string("day_06_input.txt")?;
-    let result = part1(&input);
+    let groups = parse_groups(&input)?;
+    let query = env::args().skip(1).collect::<Vec<String>>().join(" ");
+    if !query.is_empty() {
+        let query = query.parse::<Query>()?;
+        println!("{:?}: {}", query, query.evaluate(&groups));
+        return Ok(());
+    }
+    let result = part1(&groups);
     println!("part1: {}", result);
-    let result = part2(&input);
+    let result = part2(&groups);
     println!("part2: {}", result);
     Ok(())
 }
@@ -57,9 +210,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 mod test {
     use super::*;
 
-    #[test]
-    fn test_parts() {
-        let input = r#"abc
+    const INPUT: &str = r#"abc
 
 a
 b
@@ -75,8 +226,29 @@ a
 
 b
 "#;
-        assert_eq!(part1(input), 11);
-        assert_eq!(part2(input), 6);
 
+    #[test]
+    fn test_parts() -> Result<(), Box<dyn std::error::Error>> {
+        let groups = parse_groups(INPUT)?;
+        assert_eq!(part1(&groups), 11);
+        assert_eq!(part2(&groups), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_queries() -> Result<(), Box<dyn std::error::Error>> {
+        let groups = parse_groups(INPUT)?;
+        assert_eq!(groups.len(), 5);
+        assert_eq!("xor".parse::<Query>()?.evaluate(&groups), 9);
+        assert_eq!("atleast 2".parse::<Query>()?.evaluate(&groups), 2);
+        assert_eq!("atleast 0".parse::<Query>()?, Query::AtLeast(0));
+        assert_eq!("atleast 0".parse::<Query>()?.evaluate(&groups), part1(&groups));
+        assert_eq!("freq a".parse::<Query>()?.evaluate(&groups), 1 + 1 + 2 + 4);
+        assert_eq!(groups[2].frequencies()[..3], [2, 1, 1]);
+        assert_eq!(format!("{:?}", groups[2].union()), "{abc}");
+        assert!("freq 1".parse::<Query>().is_err());
+        assert!("union all".parse::<Query>().is_err());
+        assert!(parse_groups("ab\na1\n").is_err());
+        Ok(())
     }
 }
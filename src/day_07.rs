@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::{env, fmt::{self, Write}, collections::{BTreeMap, HashMap, HashSet, VecDeque}};
 use adventofcode2020::{
     ReadError,
     parse_input_file
@@ -10,28 +10,359 @@ struct Rule {
     contains: Vec<(usize, String)>
 }
 
-struct Tree(HashMap<String, Vec<(usize, String)>>);
+#[derive(Debug)]
+enum BagGraphError {
+    UnknownColor(String),
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for BagGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BagGraphError::UnknownColor(color) => write!(f, "unknown bag color: {}", color),
+            BagGraphError::Cycle(colors) => write!(f, "bag rules contain a cycle: {}", colors.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for BagGraphError {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Unvisited,
+    InProgress,
+    Done,
+}
+
+#[derive(Default)]
+struct BagGraph {
+    colors: Vec<String>,
+    ids: HashMap<String, usize>,
+    // id -> (quantity, child id)
+    contains: Vec<Vec<(usize, usize)>>,
+    contained_by: Vec<Vec<usize>>,
+}
+
+impl From<Vec<Rule>> for BagGraph {
+    fn from(rules: Vec<Rule>) -> BagGraph {
+        let mut graph = BagGraph::default();
+        for rule in rules {
+            let container = graph.intern(&rule.container);
+            for (no, color) in rule.contains {
+                let child = graph.intern(&color);
+                graph.contains[container].push((no, child));
+                graph.contained_by[child].push(container);
+            }
+        }
+        graph
+    }
+}
+
+impl BagGraph {
+    fn intern(&mut self, color: &str) -> usize {
+        if let Some(&id) = self.ids.get(color) {
+            return id;
+        }
+        let id = self.colors.len();
+        self.colors.push(color.to_string());
+        self.ids.insert(color.to_string(), id);
+        self.contains.push(vec![]);
+        self.contained_by.push(vec![]);
+        id
+    }
+
+    fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    fn id(&self, color: &str) -> Result<usize, BagGraphError> {
+        self.ids.get(color)
+            .copied()
+            .ok_or_else(|| BagGraphError::UnknownColor(color.to_string()))
+    }
+
+    fn color(&self, id: usize) -> &str {
+        &self.colors[id]
+    }
+
+    fn reachable<F>(&self, start: usize, edges: F) -> HashSet<usize>
+    where F: Fn(usize) -> Vec<usize>
+    {
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<usize> = edges(start).into();
+        while let Some(id) = queue.pop_front() {
+            if seen.insert(id) {
+                queue.extend(edges(id));
+            }
+        }
+        seen
+    }
+
+    // all colors that can eventually contain `color`
+    fn ancestors(&self, color: &str) -> Result<HashSet<usize>, BagGraphError> {
+        let id = self.id(color)?;
+        Ok(self.reachable(id, |id| self.contained_by[id].clone()))
+    }
+
+    // all colors that `color` eventually contains
+    fn descendants(&self, color: &str) -> Result<HashSet<usize>, BagGraphError> {
+        let id = self.id(color)?;
+        Ok(self.reachable(id, |id| self.contains[id].iter().map(|&(_, child)| child).collect()))
+    }
+
+    // depth first walk of the contents of `roots`, returning each bag only
+    // after all the bags it contains
+    fn postorder<I>(&self, roots: I) -> Result<Vec<usize>, BagGraphError>
+    where I: IntoIterator<Item = usize>
+    {
+        self.postorder_within(roots, |_| true)
+    }
+
+    // as `postorder`, but only following rules into bags for which `within`
+    // holds, so that cycles elsewhere don't matter
+    fn postorder_within<I, F>(&self, roots: I, within: F) -> Result<Vec<usize>, BagGraphError>
+    where I: IntoIterator<Item = usize>, F: Fn(usize) -> bool
+    {
+        let mut marks = vec![Mark::Unvisited; self.len()];
+        let mut order = Vec::with_capacity(self.len());
+        for root in roots {
+            if marks[root] != Mark::Unvisited {
+                continue;
+            }
+            marks[root] = Mark::InProgress;
+            let mut stack = vec![(root, 0)];
+            while let Some(&(id, next)) = stack.last() {
+                if let Some(&(_, child)) = self.contains[id].get(next) {
+                    let top = stack.len() - 1;
+                    stack[top].1 += 1;
+                    if !within(child) {
+                        continue;
+                    }
+                    match marks[child] {
+                        Mark::Unvisited => {
+                            marks[child] = Mark::InProgress;
+                            stack.push((child, 0));
+                        },
+                        Mark::InProgress => {
+                            let start = stack.iter().position(|&(id, _)| id == child).unwrap();
+                            let cycle = stack[start..].iter()
+                                .map(|&(id, _)| id)
+                                .chain(std::iter::once(child))
+                                .map(|id| self.color(id).to_string())
+                                .collect();
+                            return Err(BagGraphError::Cycle(cycle));
+                        },
+                        Mark::Done => {}
+                    }
+                } else {
+                    marks[id] = Mark::Done;
+                    order.push(id);
+                    stack.pop();
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    // every color, with containers ordered before the bags they contain
+    fn topological_order(&self) -> Result<Vec<usize>, BagGraphError> {
+        let mut order = self.postorder(0..self.len())?;
+        order.reverse();
+        Ok(order)
+    }
+
+    fn count_bags(&self, color: &str) -> Result<usize, BagGraphError> {
+        let id = self.id(color)?;
+        Ok(self.nested_counts(std::iter::once(id))?[id])
+    }
+
+    // total number of bags nested inside each of `roots` and everything they
+    // contain, with 0 for every other color
+    fn nested_counts<I>(&self, roots: I) -> Result<Vec<usize>, BagGraphError>
+    where I: IntoIterator<Item = usize>
+    {
+        let mut counts = vec![0; self.len()];
+        for bag in self.postorder(roots)? {
+            counts[bag] = self.contains[bag].iter()
+                .map(|&(no, child)| no + no * counts[child])
+                .sum();
+        }
+        Ok(counts)
+    }
+
+    fn packing_manifest(&self, color: &str) -> Result<Manifest, BagGraphError> {
+        let id = self.id(color)?;
+        // rejects cyclic rules before the recursive expansion below
+        self.postorder(std::iter::once(id))?;
+        Ok(self.expand(id, 1))
+    }
+
+    fn expand(&self, id: usize, quantity: usize) -> Manifest {
+        Manifest {
+            color: self.color(id).to_string(),
+            quantity,
+            contents: self.contains[id].iter()
+                .map(|&(no, child)| self.expand(child, quantity * no))
+                .collect(),
+        }
+    }
+
+    // outermost colors (those no other bag can hold) that eventually hold at
+    // least `n` bags of `color`, along with how many they hold
+    fn outermost_holding(&self, n: usize, color: &str) -> Result<Vec<(usize, usize)>, BagGraphError> {
+        let target = self.id(color)?;
+        // only bags that can hold `target` hold any, so the rest of the
+        // graph is never walked
+        let holders = self.ancestors(color)?;
+        let mut held = vec![0; self.len()];
+        for bag in self.postorder_within(holders.iter().copied(), |id| holders.contains(&id))? {
+            held[bag] = self.contains[bag].iter()
+                .map(|&(no, child)| no * (held[child] + if child == target { 1 } else { 0 }))
+                .sum();
+        }
+        let mut outermost: Vec<(usize, usize)> = holders.into_iter()
+           .filter(|&id| self.contained_by[id].is_empty() && held[id] >= n && held[id] > 0)
+           .map(|id| (id, held[id]))
+           .collect();
+        outermost.sort_unstable();
+        Ok(outermost)
+    }
+
+    // the container with the fewest nested bags that eventually holds both
+    // `a` and `b`
+    fn smallest_common_container(&self, a: &str, b: &str) -> Result<Option<usize>, BagGraphError> {
+        let a = self.ancestors(a)?;
+        let b = self.ancestors(b)?;
+        let common: Vec<usize> = a.intersection(&b).copied().collect();
+        let counts = self.nested_counts(common.iter().copied())?;
+        Ok(common.into_iter()
+           .min_by(|&x, &y| counts[x].cmp(&counts[y]).then_with(|| self.color(x).cmp(self.color(y)))))
+    }
+
+    fn subgraph(&self, subgraph: &Subgraph) -> Result<Vec<usize>, BagGraphError> {
+        let mut nodes: Vec<usize> = match subgraph {
+            Subgraph::All => return Ok((0..self.len()).collect()),
+            Subgraph::From(color) => self.descendants(color)?.into_iter().collect(),
+            Subgraph::To(color) => self.ancestors(color)?.into_iter().collect(),
+        };
+        match subgraph {
+            Subgraph::From(color) | Subgraph::To(color) => nodes.push(self.id(color)?),
+            Subgraph::All => {}
+        }
+        nodes.sort_unstable();
+        nodes.dedup();
+        Ok(nodes)
+    }
+
+    // (container, quantity, child) for every rule between two bags in `nodes`
+    fn edges<'a>(&'a self, nodes: &'a [usize]) -> impl Iterator<Item = (usize, usize, usize)> + 'a {
+        nodes.iter().flat_map(move |&container| {
+            self.contains[container].iter()
+                .filter(move |(_, child)| nodes.binary_search(child).is_ok())
+                .map(move |&(no, child)| (container, no, child))
+        })
+    }
+
+    fn to_dot(&self, subgraph: &Subgraph) -> Result<String, BagGraphError> {
+        let nodes = self.subgraph(subgraph)?;
+        let mut out = String::from("digraph bags {\n");
+        for &id in &nodes {
+            writeln!(out, "    \"{}\";", escape_dot(self.color(id))).unwrap();
+        }
+        for (container, no, child) in self.edges(&nodes) {
+            writeln!(
+                out, "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                escape_dot(self.color(container)), escape_dot(self.color(child)), no
+            ).unwrap();
+        }
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    fn to_graphml(&self, subgraph: &Subgraph) -> Result<String, BagGraphError> {
+        let nodes = self.subgraph(subgraph)?;
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        out.push_str("  <key id=\"color\" for=\"node\" attr.name=\"color\" attr.type=\"string\"/>\n");
+        out.push_str("  <key id=\"quantity\" for=\"edge\" attr.name=\"quantity\" attr.type=\"int\"/>\n");
+        out.push_str("  <graph id=\"bags\" edgedefault=\"directed\">\n");
+        for &id in &nodes {
+            writeln!(
+                out, "    <node id=\"n{}\"><data key=\"color\">{}</data></node>",
+                id, escape_xml(self.color(id))
+            ).unwrap();
+        }
+        for (container, no, child) in self.edges(&nodes) {
+            writeln!(
+                out, "    <edge source=\"n{}\" target=\"n{}\"><data key=\"quantity\">{}</data></edge>",
+                container, child, no
+            ).unwrap();
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        Ok(out)
+    }
+}
+
+struct Manifest {
+    color: String,
+    // number of these bags at this point of the packing, across all of
+    // their containers
+    quantity: usize,
+    contents: Vec<Manifest>,
+}
+
+impl Manifest {
+    fn total(&self) -> usize {
+        self.contents.iter().map(|m| m.quantity + m.total()).sum()
+    }
 
-impl From<Vec<Rule>> for Tree {
-    fn from(rules: Vec<Rule>) -> Tree {
-        Tree(rules.into_iter()
-             .map(|r| (r.container, r.contains))
-             .collect())
+    // quantities of each color at each depth, with the outer bag at depth 0
+    fn by_depth(&self) -> Vec<BTreeMap<String, usize>> {
+        let mut depths = vec![];
+        let mut level = vec![self];
+        while !level.is_empty() {
+            let mut counts = BTreeMap::new();
+            for m in &level {
+                *counts.entry(m.color.clone()).or_insert(0) += m.quantity;
+            }
+            depths.push(counts);
+            level = level.iter().flat_map(|m| m.contents.iter()).collect();
+        }
+        depths
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        writeln!(f, "{:indent$}{} {}", "", self.quantity, self.color, indent = depth * 2)?;
+        self.contents.iter().try_for_each(|m| m.fmt_indented(f, depth + 1))
     }
 }
 
-impl Tree {
-    fn count_bags(&self, color: &str) -> usize {
-        self.0.get(color)
-            .map(|children| {
-                children.iter().map(|(no, color)| {
-                    no + (no * self.count_bags(color))
-                }).sum()
-            })
-            .unwrap_or(0)
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
     }
 }
 
+enum Subgraph<'a> {
+    All,
+    // everything `color` eventually contains
+    From(&'a str),
+    // everything that can eventually contain `color`
+    To(&'a str),
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 fn parse_rule(line_no: usize, line: String) -> Result<Rule, ReadError> {
     let mut s = line.split(" bags contain ");
     let container = s.next()
@@ -63,57 +394,78 @@ fn parse_rule(line_no: usize, line: String) -> Result<Rule, ReadError> {
     Ok(Rule { container, contains })
 }
 
-fn build_tree(rules: Vec<Rule>) -> HashMap<String, HashSet<String>> {
-    let mut result: HashMap<String, HashSet<String>> = HashMap::new();
-
-    for rule in rules {
-        for contains in rule.contains {
-            if let Some(entry) = result.get_mut(&contains.1) {
-                entry.insert(rule.container.clone());
-            } else {
-                let mut entry = HashSet::new();
-                entry.insert(rule.container.clone());
-                result.insert(contains.1, entry);
-            }
-        }
-    }
-
-    result
-}
-
-fn traverse_tree(color: &str, tree: &mut HashMap<String, HashSet<String>>) -> HashSet<String> {
-    if let Some(parents) = tree.remove(color) {
-        parents.into_iter().map(|color| {
-            let mut r = traverse_tree(&color, tree);
-            r.insert(color);
-            r
-        })
-            .fold(HashSet::new(), |mut acc, set| {
-                acc.extend(set.into_iter());
-                acc
-            })
-    } else {
-        HashSet::new()
-    }
-
-}
-
-fn part1(mut tree: HashMap<String, HashSet<String>>) -> usize {
-    let colors = traverse_tree("shiny gold", &mut tree);
-    colors.len()
+fn part1(graph: &BagGraph) -> Result<usize, BagGraphError> {
+    Ok(graph.ancestors("shiny gold")?.len())
 }
 
-fn part2(tree: Tree) -> usize {
-    tree.count_bags("shiny gold")
+fn part2(graph: &BagGraph) -> Result<usize, BagGraphError> {
+    graph.count_bags("shiny gold")
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let rules: Vec<Rule> = parse_input_file("day_07_input.txt", parse_rule)?;
-    let tree = build_tree(rules.clone());
-    let result = part1(tree);
+    let graph: BagGraph = parse_input_file("day_07_input.txt", parse_rule)?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(format) = args.first().filter(|f| *f == "dot" || *f == "graphml") {
+        // e.g. `dot`, `graphml from shiny gold`, `dot to shiny gold`
+        let color = args[2.min(args.len())..].join(" ");
+        let subgraph = match args.get(1).map(|s| s.as_str()) {
+            None => Subgraph::All,
+            Some("from") => Subgraph::From(&color),
+            Some("to") => Subgraph::To(&color),
+            Some(other) => return Err(format!("expected from or to, got: {}", other).into()),
+        };
+        if format == "dot" {
+            print!("{}", graph.to_dot(&subgraph)?);
+        } else {
+            print!("{}", graph.to_graphml(&subgraph)?);
+        }
+        return Ok(());
+    }
+    match args.first().map(|s| s.as_str()) {
+        Some("manifest") => {
+            // e.g. `manifest shiny gold`
+            let manifest = graph.packing_manifest(&args[1..].join(" "))?;
+            print!("{}", manifest);
+            for (depth, counts) in manifest.by_depth().iter().enumerate().skip(1) {
+                let counts: Vec<String> = counts.iter().map(|(c, n)| format!("{} {}", n, c)).collect();
+                println!("depth {}: {}", depth, counts.join(", "));
+            }
+            println!("total: {}", manifest.total());
+            return Ok(());
+        },
+        Some("holds") => {
+            // e.g. `holds 10 shiny gold`
+            let n = args.get(1).and_then(|n| n.parse::<usize>().ok())
+                .ok_or("expected a count")?;
+            for (id, held) in graph.outermost_holding(n, &args[2..].join(" "))? {
+                println!("{}: {}", graph.color(id), held);
+            }
+            return Ok(());
+        },
+        Some("smallest") => {
+            // e.g. `smallest shiny gold, dark olive`
+            let joined = args[1..].join(" ");
+            let (a, b) = joined.split_once(',').ok_or("expected two colors separated by a comma")?;
+            match graph.smallest_common_container(a.trim(), b.trim())? {
+                Some(id) => println!("{}", graph.color(id)),
+                None => println!("no container holds both"),
+            }
+            return Ok(());
+        },
+        _ => {}
+    }
+    let color = args.join(" ");
+    if !color.is_empty() {
+        let id = graph.id(&color)?;
+        let order = graph.topological_order()?;
+        println!("{}: topological position {} of {}", color, order.iter().position(|&i| i == id).unwrap(), order.len());
+        println!("contained by: {}", graph.ancestors(&color)?.len());
+        println!("contains: {} colors, {} bags", graph.descendants(&color)?.len(), graph.count_bags(&color)?);
+        return Ok(());
+    }
+    let result = part1(&graph)?;
     println!("part1: {}", result);
-    let tree: Tree = rules.into();
-    let result = part2(tree);
+    let result = part2(&graph)?;
     println!("part2: {}", result);
     Ok(())
 }
@@ -123,9 +475,7 @@ mod test {
     use super::*;
     use adventofcode2020::parse_input_lines;
 
-    #[test]
-    fn test_1() -> Result<(), Box<dyn std::error::Error>> {
-        let input = r#"light red bags contain 1 bright white bag, 2 muted yellow bags.
+    const EXAMPLE: &str = r#"light red bags contain 1 bright white bag, 2 muted yellow bags.
 dark orange bags contain 3 bright white bags, 4 muted yellow bags.
 bright white bags contain 1 shiny gold bag.
 muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
@@ -136,13 +486,26 @@ faded blue bags contain no other bags.
 dotted black bags contain no other bags.
 "#;
 
-        let rules: Vec<Rule> = parse_input_lines(input.as_bytes(), parse_rule)?;
+    #[test]
+    fn test_1() -> Result<(), Box<dyn std::error::Error>> {
+        let rules: Vec<Rule> = parse_input_lines(EXAMPLE.as_bytes(), parse_rule)?;
         assert_eq!(rules.len(), 9);
-        let tree = build_tree(rules.clone());
-        assert_eq!(part1(tree), 4);
+        let graph: BagGraph = rules.into();
+        assert_eq!(part1(&graph)?, 4);
+        assert_eq!(part2(&graph)?, 32);
+
+        let descendants = graph.descendants("shiny gold")?;
+        assert_eq!(descendants.len(), 4);
+        assert!(descendants.contains(&graph.id("dotted black")?));
 
-        let tree: Tree = rules.into();
-        assert_eq!(part2(tree), 32);
+        let order = graph.topological_order()?;
+        assert_eq!(order.len(), 9);
+        let position = |color: &str| order.iter().position(|&id| id == graph.id(color).unwrap());
+        for (container, contains) in graph.contains.iter().enumerate() {
+            for &(_, child) in contains {
+                assert!(position(graph.color(container)) < position(graph.color(child)));
+            }
+        }
 
         Ok(())
     }
@@ -157,8 +520,105 @@ dark green bags contain 2 dark blue bags.
 dark blue bags contain 2 dark violet bags.
 dark violet bags contain no other bags.
 "#;
-        let tree: Tree = parse_input_lines(input.as_bytes(), parse_rule)?;
-        assert_eq!(part2(tree), 126);
+        let graph: BagGraph = parse_input_lines(input.as_bytes(), parse_rule)?;
+        assert_eq!(part2(&graph)?, 126);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export() -> Result<(), Box<dyn std::error::Error>> {
+        let input = r#"light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag.
+dark olive bags contain no other bags.
+faded blue bags contain no other bags.
+"#;
+        let graph: BagGraph = parse_input_lines(input.as_bytes(), parse_rule)?;
+
+        let dot = graph.to_dot(&Subgraph::From("shiny gold"))?;
+        assert_eq!(dot, r#"digraph bags {
+    "shiny gold";
+    "dark olive";
+    "shiny gold" -> "dark olive" [label="1"];
+}
+"#);
+
+        let dot = graph.to_dot(&Subgraph::To("shiny gold"))?;
+        assert!(dot.contains(r#""muted yellow" -> "shiny gold" [label="2"];"#));
+        assert!(!dot.contains("faded blue"));
+        assert!(!dot.contains("dark olive"));
+
+        let graphml = graph.to_graphml(&Subgraph::All)?;
+        assert_eq!(graphml.matches("<node ").count(), 6);
+        assert_eq!(graphml.matches("<edge ").count(), 6);
+        assert!(graphml.contains(r#"<edge source="n2" target="n3"><data key="quantity">2</data></edge>"#));
+
+        assert!(graph.to_dot(&Subgraph::From("plaid")).is_err());
+        assert_eq!(escape_xml("a<b & \"c\">"), "a&lt;b &amp; &quot;c&quot;&gt;");
+        Ok(())
+    }
+
+    #[test]
+    fn test_packing() -> Result<(), Box<dyn std::error::Error>> {
+        let graph: BagGraph = parse_input_lines(EXAMPLE.as_bytes(), parse_rule)?;
+
+        let manifest = graph.packing_manifest("shiny gold")?;
+        assert_eq!(manifest.total(), 32);
+        assert_eq!(manifest.to_string(), r#"1 shiny gold
+  1 dark olive
+    3 faded blue
+    4 dotted black
+  2 vibrant plum
+    10 faded blue
+    12 dotted black
+"#);
+        let depths = manifest.by_depth();
+        assert_eq!(depths.len(), 3);
+        assert_eq!(depths[2]["faded blue"], 13);
+        assert_eq!(depths[2]["dotted black"], 16);
+
+        let holding = graph.outermost_holding(5, "shiny gold")?;
+        let holding: Vec<(&str, usize)> = holding.into_iter().map(|(id, n)| (graph.color(id), n)).collect();
+        assert_eq!(holding, vec![("light red", 5), ("dark orange", 11)]);
+        assert_eq!(graph.outermost_holding(6, "shiny gold")?.len(), 1);
+        assert_eq!(graph.outermost_holding(0, "light red")?.len(), 0);
+
+        let smallest = graph.smallest_common_container("dark olive", "faded blue")?;
+        assert_eq!(smallest.map(|id| graph.color(id)), Some("shiny gold"));
+        let smallest = graph.smallest_common_container("shiny gold", "faded blue")?;
+        assert_eq!(smallest.map(|id| graph.color(id)), Some("bright white"));
+        assert_eq!(graph.smallest_common_container("light red", "faded blue")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cycle() -> Result<(), Box<dyn std::error::Error>> {
+        let input = r#"shiny gold bags contain 2 dark red bags.
+dark red bags contain 1 dark orange bag, 3 faded blue bags.
+dark orange bags contain 1 shiny gold bag.
+faded blue bags contain no other bags.
+light red bags contain 2 muted yellow bags, 1 dotted black bag.
+muted yellow bags contain 3 dotted black bags.
+dotted black bags contain no other bags.
+"#;
+        let graph: BagGraph = parse_input_lines(input.as_bytes(), parse_rule)?;
+        match graph.count_bags("shiny gold") {
+            Err(BagGraphError::Cycle(colors)) => {
+                assert_eq!(colors, vec!["shiny gold", "dark red", "dark orange", "shiny gold"]);
+            },
+            r => panic!("expected cycle, got {:?}", r),
+        }
+        assert!(graph.topological_order().is_err());
+        assert_eq!(graph.count_bags("faded blue")?, 0);
+        assert!(matches!(graph.count_bags("plaid"), Err(BagGraphError::UnknownColor(_))));
+
+        // queries that don't reach the cycle aren't affected by it
+        let holding = graph.outermost_holding(1, "dotted black")?;
+        assert_eq!(holding, vec![(graph.id("light red")?, 7)]);
+        let smallest = graph.smallest_common_container("muted yellow", "dotted black")?;
+        assert_eq!(smallest, graph.id("light red").ok());
+        assert!(graph.outermost_holding(1, "faded blue").is_err());
         Ok(())
     }
 }
@@ -1,64 +1,484 @@
+use std::{env, fs, convert::TryFrom, fmt::{self, Write}, io::{self, BufRead}, collections::{BTreeMap, HashMap, HashSet, VecDeque}};
 use adventofcode2020::{ReadError, parse_input_file};
 
-#[derive(Debug)]
+const REGISTER_NAMES: [&str; 4] = ["acc", "b", "c", "d"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Register(usize);
+
+impl Register {
+    const ACC: Register = Register(0);
+
+    fn parse(name: &str) -> Option<Register> {
+        REGISTER_NAMES.iter().position(|&r| r == name).map(Register)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", REGISTER_NAMES[self.0])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Register(Register),
+    Immediate(i32),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Register(r) => write!(f, "{}", r),
+            Value::Immediate(val) => write!(f, "{:+}", val),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArithOp {
+    Set,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+// conditions on a register for the conditional jumps
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    Zero,
+    NonZero,
+    Negative,
+    Positive,
+}
+
+impl Condition {
+    fn holds(&self, val: i32) -> bool {
+        match self {
+            Condition::Zero => val == 0,
+            Condition::NonZero => val != 0,
+            Condition::Negative => val < 0,
+            Condition::Positive => val > 0,
+        }
+    }
+}
+
+const ARITH_OPCODES: [(&str, ArithOp); 6] = [
+    ("set", ArithOp::Set),
+    ("add", ArithOp::Add),
+    ("sub", ArithOp::Sub),
+    ("mul", ArithOp::Mul),
+    ("div", ArithOp::Div),
+    ("mod", ArithOp::Mod),
+];
+
+const JUMP_OPCODES: [(&str, Condition); 4] = [
+    ("jz", Condition::Zero),
+    ("jnz", Condition::NonZero),
+    ("jlz", Condition::Negative),
+    ("jgz", Condition::Positive),
+];
+
+const OPCODES: [&str; 19] = [
+    "acc", "jmp", "nop",
+    "set", "add", "sub", "mul", "div", "mod",
+    "jz", "jnz", "jlz", "jgz",
+    "push", "pop", "call", "ret", "in", "out",
+];
+
+// The original handheld instruction set is `acc`, `jmp` and `nop`, operating
+// on the accumulator. The extensions add the registers `b`, `c` and `d`
+// (`acc` is the first register), arithmetic, conditional jumps relative to
+// the current instruction, a stack shared by `push`/`pop` and `call`/`ret`,
+// and `in`/`out` on the input and output queues.
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Instruction {
     Acc(i32),
     Jmp(i32),
     Nop(i32),
+    Arith(ArithOp, Register, Value),
+    JumpIf(Condition, Register, i32),
+    Push(Value),
+    Pop(Register),
+    Call(i32),
+    Ret,
+    In(Register),
+    Out(Value),
+}
+
+impl Instruction {
+    fn new(opcode: &str, operands: &[Value]) -> Option<Instruction> {
+        use Value::{Immediate, Register as Reg};
+        let arith = ARITH_OPCODES.iter().find(|(o, _)| *o == opcode).map(|(_, op)| *op);
+        let jump = JUMP_OPCODES.iter().find(|(o, _)| *o == opcode).map(|(_, cond)| *cond);
+        match (opcode, operands) {
+            ("acc", [Immediate(val)]) => Some(Instruction::Acc(*val)),
+            ("jmp", [Immediate(val)]) => Some(Instruction::Jmp(*val)),
+            ("nop", [Immediate(val)]) => Some(Instruction::Nop(*val)),
+            (_, [Reg(r), val]) if arith.is_some() => Some(Instruction::Arith(arith.unwrap(), *r, *val)),
+            (_, [Reg(r), Immediate(val)]) if jump.is_some() => Some(Instruction::JumpIf(jump.unwrap(), *r, *val)),
+            ("push", [val]) => Some(Instruction::Push(*val)),
+            ("pop", [Reg(r)]) => Some(Instruction::Pop(*r)),
+            ("call", [Immediate(val)]) => Some(Instruction::Call(*val)),
+            ("ret", []) => Some(Instruction::Ret),
+            ("in", [Reg(r)]) => Some(Instruction::In(*r)),
+            ("out", [val]) => Some(Instruction::Out(*val)),
+            _ => None
+        }
+    }
+
+    fn opcode(&self) -> &'static str {
+        match self {
+            Instruction::Acc(_) => "acc",
+            Instruction::Jmp(_) => "jmp",
+            Instruction::Nop(_) => "nop",
+            Instruction::Arith(op, _, _) => ARITH_OPCODES.iter().find(|(_, o)| o == op).unwrap().0,
+            Instruction::JumpIf(cond, _, _) => JUMP_OPCODES.iter().find(|(_, c)| c == cond).unwrap().0,
+            Instruction::Push(_) => "push",
+            Instruction::Pop(_) => "pop",
+            Instruction::Call(_) => "call",
+            Instruction::Ret => "ret",
+            Instruction::In(_) => "in",
+            Instruction::Out(_) => "out",
+        }
+    }
+
+    // the relative jump offset of instructions that can transfer control,
+    // including the dormant offset of `nop`
+    fn jump_offset(&self) -> Option<i32> {
+        match self {
+            Instruction::Jmp(val) | Instruction::Nop(val) | Instruction::Call(val) => Some(*val),
+            Instruction::JumpIf(_, _, val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    fn is_basic(&self) -> bool {
+        matches!(self, Instruction::Acc(_) | Instruction::Jmp(_) | Instruction::Nop(_))
+    }
+
+    // the instruction with jmp and nop swapped
+    fn flipped(&self) -> Option<Instruction> {
+        match self {
+            Instruction::Jmp(val) => Some(Instruction::Nop(*val)),
+            Instruction::Nop(val) => Some(Instruction::Jmp(*val)),
+            _ => None,
+        }
+    }
+
+    // renders the instruction, with `target` in place of the jump offset
+    fn render(&self, target: Option<&str>) -> String {
+        let offset = |val: &i32| target.map(|t| t.to_string()).unwrap_or_else(|| format!("{:+}", val));
+        match self {
+            Instruction::Acc(val) => format!("acc {:+}", val),
+            Instruction::Jmp(val) | Instruction::Nop(val) | Instruction::Call(val) => {
+                format!("{} {}", self.opcode(), offset(val))
+            },
+            Instruction::Arith(_, r, val) => format!("{} {} {}", self.opcode(), r, val),
+            Instruction::JumpIf(_, r, val) => format!("{} {} {}", self.opcode(), r, offset(val)),
+            Instruction::Push(val) | Instruction::Out(val) => format!("{} {}", self.opcode(), val),
+            Instruction::Pop(r) | Instruction::In(r) => format!("{} {}", self.opcode(), r),
+            Instruction::Ret => "ret".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(None))
+    }
 }
 
-struct Program(Vec<Instruction>);
+#[derive(Default)]
+struct Program {
+    instructions: Vec<Instruction>,
+    // address -> names in source order, for labels defined in assembled
+    // source
+    labels: BTreeMap<usize, Vec<String>>,
+}
 
 impl From<Vec<Instruction>> for Program {
     fn from(instructions: Vec<Instruction>) -> Program {
-        Program(instructions)
+        Program { instructions, labels: BTreeMap::new() }
     }
 }
 
 impl Program {
     fn len(&self) -> usize {
-        self.0.len()
+        self.instructions.len()
     }
 
-    fn instruction_at<'a>(&'a self, pointer: usize) -> Option<&'a Instruction> {
-        self.0.get(pointer)
+    fn instruction_at(&self, pointer: usize) -> Option<&Instruction> {
+        self.instructions.get(pointer)
     }
 
-    fn swap_instruction<'a>(&'a mut self, pointer: usize) -> Result<(), ()> {
-        if pointer < self.0.len() {
-            let new = match self.0[pointer] {
-                Instruction::Acc(_) => return Err(()),
-                Instruction::Jmp(val) => Instruction::Nop(val),
-                Instruction::Nop(val) => Instruction::Jmp(val),
-            };
-            let _ = std::mem::replace(&mut self.0[pointer], new);
-            Ok(())
-        } else {
-            Err(())
-        }
+    // whether the program only uses the original acc/jmp/nop instructions
+    fn is_basic(&self) -> bool {
+        self.instructions.iter().all(Instruction::is_basic)
+    }
+
+    // FNV-1a hash of the program's instructions, used to check snapshots are
+    // restored against the program they were taken from
+    fn hash(&self) -> u64 {
+        self.instructions.iter()
+            .flat_map(|instruction| instruction.to_string().into_bytes().into_iter().chain(Some(b'\n')))
+            .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
     }
 }
 
 fn parse_instruction(line_no: usize, line: String) -> Result<Instruction, ReadError> {
-    let val = line[4..].parse::<i32>()
-        .map_err(|_| ReadError::ParseError(line_no, line.clone()))?;
-    match &line[..3] {
-        "acc" => {
-            Ok(Instruction::Acc(val))
-        },
-        "jmp" => {
-            Ok(Instruction::Jmp(val))
-        },
-        "nop" => Ok(Instruction::Nop(val)),
-        _ => Err(ReadError::ParseError(line_no, line))
+    let mut parts = line.split_whitespace();
+    let opcode = parts.next();
+    let operands = parts
+        .map(|part| {
+            Register::parse(part).map(Value::Register)
+                .or_else(|| part.parse::<i32>().ok().map(Value::Immediate))
+        })
+        .collect::<Option<Vec<Value>>>();
+    match (opcode, operands) {
+        (Some(opcode), Some(operands)) => Instruction::new(opcode, &operands),
+        _ => None
+    }.ok_or(ReadError::ParseError(line_no, line))
+}
+
+#[derive(Debug, PartialEq)]
+enum AsmError {
+    Syntax(usize, String),
+    UnknownOpcode(usize, String),
+    UnknownDirective(usize, String),
+    UndefinedSymbol(usize, String),
+    DuplicateSymbol(usize, String),
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Copy)]
+enum Operand<'a> {
+    Value(i32),
+    Register(Register),
+    Symbol(&'a str),
+}
+
+fn parse_operand(line_no: usize, operand: &str) -> Result<Operand<'_>, AsmError> {
+    if let Ok(val) = operand.parse::<i32>() {
+        Ok(Operand::Value(val))
+    } else if let Some(r) = Register::parse(operand) {
+        Ok(Operand::Register(r))
+    } else if is_symbol(operand) {
+        Ok(Operand::Symbol(operand))
+    } else {
+        Err(AsmError::Syntax(line_no, operand.to_string()))
+    }
+}
+
+fn parse_operands<'a>(line_no: usize, operands: &[&'a str]) -> Result<Vec<Operand<'a>>, AsmError> {
+    operands.iter().map(|operand| parse_operand(line_no, operand)).collect()
+}
+
+fn is_symbol(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Assembles boot code source into a program.
+//
+// Each line holds an optional `label:`, then an instruction or a directive,
+// then an optional `;` or `#` comment. Operands are registers, signed
+// integers or symbols: a label operand is converted into the offset from the
+// current instruction to the label, any other symbol must be defined with
+// `.equ`. Register names can't be used as symbols.
+//
+// Directives:
+//   .equ name value           define a constant
+//   .fill count op args...    emit `op args...` count times
+fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut label_names: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut constants: HashMap<&str, i32> = HashMap::new();
+    // (line_no, opcode, operands) for each address
+    let mut pending = vec![];
+
+    for (line_no, line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let mut line = line.split([';', '#']).next().unwrap().trim();
+        while let Some(idx) = line.find(':') {
+            let label = line[..idx].trim();
+            if !is_symbol(label) || Register::parse(label).is_some() {
+                return Err(AsmError::Syntax(line_no, label.to_string()));
+            }
+            if labels.insert(label, pending.len()).is_some() || constants.contains_key(label) {
+                return Err(AsmError::DuplicateSymbol(line_no, label.to_string()));
+            }
+            label_names.entry(pending.len()).or_default().push(label.to_string());
+            line = line[idx + 1..].trim();
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            [] => {},
+            [".equ", name, value] => {
+                let value = match parse_operand(line_no, value)? {
+                    Operand::Value(val) => val,
+                    Operand::Symbol(sym) => *constants.get(sym)
+                        .ok_or_else(|| AsmError::UndefinedSymbol(line_no, sym.to_string()))?,
+                    Operand::Register(_) => return Err(AsmError::Syntax(line_no, value.to_string())),
+                };
+                if !is_symbol(name) || Register::parse(name).is_some() {
+                    return Err(AsmError::Syntax(line_no, name.to_string()));
+                }
+                if constants.insert(name, value).is_some() || labels.contains_key(name) {
+                    return Err(AsmError::DuplicateSymbol(line_no, name.to_string()));
+                }
+            },
+            [".fill", count, opcode, operands @ ..] => {
+                let count = count.parse::<usize>()
+                    .map_err(|_| AsmError::Syntax(line_no, count.to_string()))?;
+                let operands = parse_operands(line_no, operands)?;
+                for _ in 0..count {
+                    pending.push((line_no, *opcode, operands.clone()));
+                }
+            },
+            [directive, ..] if directive.starts_with('.') => {
+                return Err(AsmError::UnknownDirective(line_no, directive.to_string()));
+            },
+            [opcode, operands @ ..] => {
+                pending.push((line_no, *opcode, parse_operands(line_no, operands)?));
+            },
+        }
+    }
+
+    let instructions = pending.into_iter().enumerate()
+        .map(|(address, (line_no, opcode, operands))| {
+            if !OPCODES.contains(&opcode) {
+                return Err(AsmError::UnknownOpcode(line_no, opcode.to_string()));
+            }
+            let operands = operands.into_iter().map(|operand| match operand {
+                Operand::Value(val) => Ok(Value::Immediate(val)),
+                Operand::Register(r) => Ok(Value::Register(r)),
+                Operand::Symbol(sym) => {
+                    if let Some(&target) = labels.get(sym) {
+                        Ok(Value::Immediate(target as i32 - address as i32))
+                    } else {
+                        constants.get(sym)
+                            .map(|&val| Value::Immediate(val))
+                            .ok_or_else(|| AsmError::UndefinedSymbol(line_no, sym.to_string()))
+                    }
+                }
+            }).collect::<Result<Vec<Value>, AsmError>>()?;
+            Instruction::new(opcode, &operands)
+                .ok_or_else(|| AsmError::Syntax(line_no, opcode.to_string()))
+        })
+        .collect::<Result<Vec<Instruction>, AsmError>>()?;
+
+    Ok(Program {
+        instructions,
+        labels: label_names,
+    })
+}
+
+// Renders a program as assembler source. Jump targets (and the targets of
+// non-zero nops) get labels, reusing the program's own label names where it
+// has them and otherwise generating names that don't clash with them.
+fn disassemble(program: &Program) -> String {
+    let target = |address: usize, instruction: &Instruction| {
+        instruction.jump_offset()
+            .filter(|&val| val != 0 || !matches!(instruction, Instruction::Nop(_)))
+            .map(|val| address as i64 + val as i64)
+            .filter(|&target| target >= 0 && target <= program.len() as i64)
+            .map(|target| target as usize)
+    };
+    let mut labels = program.labels.clone();
+    let mut taken: HashSet<String> = labels.values().flatten().cloned().collect();
+    for (address, instruction) in program.instructions.iter().enumerate() {
+        if let Some(target) = target(address, instruction) {
+            labels.entry(target).or_insert_with(|| {
+                let name = std::iter::once(format!("L{}", target))
+                    .chain((1..).map(|n| format!("L{}_{}", target, n)))
+                    .find(|name| !taken.contains(name))
+                    .unwrap();
+                taken.insert(name.clone());
+                vec![name]
+            });
+        }
+    }
+
+    let mut out = String::new();
+    let write_labels = |out: &mut String, address: usize| {
+        for label in labels.get(&address).into_iter().flatten() {
+            writeln!(out, "{}:", label).unwrap();
+        }
+    };
+    for (address, instruction) in program.instructions.iter().enumerate() {
+        write_labels(&mut out, address);
+        let label = target(address, instruction)
+            .filter(|_| instruction.jump_offset() != Some(0))
+            .and_then(|target| labels.get(&target))
+            .and_then(|names| names.first());
+        writeln!(out, "    {}", instruction.render(label.map(|l| l.as_str()))).unwrap();
     }
+    write_labels(&mut out, program.len());
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LoopDetection {
+    // any instruction executing a second time is a loop, as the original
+    // handheld does
+    Revisit,
+    // a loop is the whole machine state repeating, for programs using the
+    // extended instructions to loop on purpose
+    State,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct MachineState {
+    pointer: usize,
+    registers: [i32; 4],
+    stack: Vec<i32>,
+    inputs_read: usize,
+}
+
+enum StackChange {
+    Pushed,
+    Popped(i32),
+}
+
+// what an executed instruction changed, so it can be undone
+struct Undo {
+    pointer: usize,
+    registers: [i32; 4],
+    stack: Option<StackChange>,
+    input: Option<i32>,
+    output: bool,
 }
 
 struct BootLoader<'a> {
     program: &'a Program,
-    acc: i32,
+    registers: [i32; 4],
     pointer: usize,
-    instructions_called: Vec<bool>
+    stack: Vec<i32>,
+    input: VecDeque<i32>,
+    inputs_read: usize,
+    output: Vec<i32>,
+    loop_detection: LoopDetection,
+    // instructions replacing those in `program` at the given addresses
+    patches: BTreeMap<usize, Instruction>,
+    instructions_called: Vec<bool>,
+    seen_states: HashSet<MachineState>,
+    // undo records for stepping back, only kept when `record_history` is set
+    // as they grow with every step
+    record_history: bool,
+    history: Vec<Undo>,
+    // number of times each instruction has been executed
+    hits: Vec<usize>,
+    trace: Option<Box<dyn io::Write + 'a>>,
 }
 
 #[derive(Debug)]
@@ -66,57 +486,1002 @@ enum BootLoaderError {
     InstructionOverflow(usize),
     InvalidJump(usize, i32),
     LoopDetected(usize, i32),
+    StackUnderflow(usize),
+    InputExhausted(usize),
+    ArithmeticError(usize),
+    Trace(io::Error),
 }
 
+impl fmt::Display for BootLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootLoaderError::InstructionOverflow(pointer) => write!(f, "no instruction at {}", pointer),
+            BootLoaderError::InvalidJump(pointer, val) => write!(f, "invalid jump by {} at {}", val, pointer),
+            BootLoaderError::LoopDetected(pointer, acc) => write!(f, "loop detected at {} with acc {}", pointer, acc),
+            BootLoaderError::StackUnderflow(pointer) => write!(f, "stack underflow at {}", pointer),
+            BootLoaderError::InputExhausted(pointer) => write!(f, "input exhausted at {}", pointer),
+            BootLoaderError::ArithmeticError(pointer) => write!(f, "arithmetic error at {}", pointer),
+            BootLoaderError::Trace(e) => write!(f, "failed to write trace: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BootLoaderError {}
+
+const SNAPSHOT_HEADER: &str = "bootloader snapshot v1";
+
+#[derive(Debug)]
+enum SnapshotError {
+    Parse(usize, String),
+    // the snapshot was taken running a different program
+    ProgramMismatch,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Parse(line_no, line) => write!(f, "invalid snapshot line {}: {}", line_no, line),
+            SnapshotError::ProgramMismatch => write!(f, "snapshot was taken from a different program"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
 impl<'a> BootLoader<'a> {
     fn new(program: &'a Program) -> BootLoader<'a> {
         BootLoader {
             program,
-            acc: 0,
+            registers: [0; 4],
             pointer: 0,
-            instructions_called: vec![false; program.len()]
+            stack: vec![],
+            input: VecDeque::new(),
+            inputs_read: 0,
+            output: vec![],
+            loop_detection: LoopDetection::Revisit,
+            patches: BTreeMap::new(),
+            instructions_called: vec![false; program.len()],
+            seen_states: HashSet::new(),
+            record_history: false,
+            history: vec![],
+            hits: vec![0; program.len()],
+            trace: None,
         }
     }
 
+    fn with_loop_detection(mut self, loop_detection: LoopDetection) -> BootLoader<'a> {
+        self.loop_detection = loop_detection;
+        self
+    }
+
+    fn with_patch(mut self, address: usize, instruction: Instruction) -> BootLoader<'a> {
+        self.patches.insert(address, instruction);
+        self
+    }
+
+    fn with_history(mut self) -> BootLoader<'a> {
+        self.record_history = true;
+        self
+    }
+
+    fn with_input<I: IntoIterator<Item = i32>>(mut self, input: I) -> BootLoader<'a> {
+        self.input.extend(input);
+        self
+    }
+
+    // writes a line of `pointer,instruction,acc_before,acc_after` to `trace`
+    // for every executed instruction
+    fn with_trace<W: io::Write + 'a>(mut self, mut trace: W) -> io::Result<BootLoader<'a>> {
+        writeln!(trace, "pointer,instruction,acc_before,acc_after")?;
+        self.trace = Some(Box::new(trace));
+        Ok(self)
+    }
+
+    fn acc(&self) -> i32 {
+        self.registers[Register::ACC.0]
+    }
+
+    fn instruction_at(&self, pointer: usize) -> Option<&Instruction> {
+        self.patches.get(&pointer).or_else(|| self.program.instruction_at(pointer))
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.pointer == self.program.len()
+    }
+
+    fn machine_state(&self) -> MachineState {
+        MachineState {
+            pointer: self.pointer,
+            registers: self.registers,
+            stack: self.stack.clone(),
+            inputs_read: self.inputs_read,
+        }
+    }
+
+    fn value(&self, val: &Value) -> i32 {
+        match val {
+            Value::Register(r) => self.registers[r.0],
+            Value::Immediate(val) => *val,
+        }
+    }
+
+    fn jump(&mut self, pointer: usize, val: i32) -> Result<(), BootLoaderError> {
+        let target = pointer as i64 + val as i64;
+        if target < 0 {
+            return Err(BootLoaderError::InvalidJump(pointer, val));
+        }
+        self.pointer = target as usize;
+        Ok(())
+    }
+
     fn step(&mut self) -> Result<(), BootLoaderError> {
-        if let Some(instruction) = self.program.instruction_at(self.pointer) {
-            if self.instructions_called[self.pointer] {
-                Err(BootLoaderError::LoopDetected(self.pointer, self.acc))
-            } else {
-                self.instructions_called[self.pointer] = true;
-                match instruction {
-                    Instruction::Acc(val) => {
-                        self.acc += val;
-                        self.pointer += 1;
-                        Ok(())
-                    },
-                    Instruction::Jmp(val) => {
-                        let pointer = self.pointer as i32 + val;
-                        if pointer < 0 {
-                            Err(BootLoaderError::InvalidJump(self.pointer, *val))
-                        } else {
-                            self.pointer = pointer as usize;
-                            Ok(())
-                        }
-                    },
-                    Instruction::Nop(_) => {
-                        self.pointer += 1;
-                        Ok(())
-                    },
+        let pointer = self.pointer;
+        let instruction = *self.instruction_at(pointer)
+            .ok_or(BootLoaderError::InstructionOverflow(pointer))?;
+        let state = match self.loop_detection {
+            LoopDetection::Revisit => None,
+            LoopDetection::State => Some(self.machine_state()),
+        };
+        let looped = match &state {
+            None => self.instructions_called[pointer],
+            Some(state) => self.seen_states.contains(state),
+        };
+        if looped {
+            return Err(BootLoaderError::LoopDetected(pointer, self.acc()));
+        }
+        let acc = self.acc();
+        let mut undo = Undo {
+            pointer,
+            registers: self.registers,
+            stack: None,
+            input: None,
+            output: false,
+        };
+        self.pointer += 1;
+        // a failed instruction leaves the machine as it was, so stepping
+        // again fails the same way
+        if let Err(e) = self.execute(pointer, instruction, &mut undo) {
+            self.revert(&undo);
+            return Err(e);
+        }
+        match state {
+            None => self.instructions_called[pointer] = true,
+            Some(state) => { self.seen_states.insert(state); },
+        }
+        self.hits[pointer] += 1;
+        if self.record_history {
+            self.history.push(undo);
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            writeln!(trace, "{},{},{},{}", pointer, instruction, acc, self.registers[Register::ACC.0])
+                .map_err(BootLoaderError::Trace)?;
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, pointer: usize, instruction: Instruction, undo: &mut Undo) -> Result<(), BootLoaderError> {
+        match instruction {
+            Instruction::Acc(val) => {
+                self.registers[Register::ACC.0] = self.acc().checked_add(val)
+                    .ok_or(BootLoaderError::ArithmeticError(pointer))?;
+            },
+            Instruction::Jmp(val) => self.jump(pointer, val)?,
+            Instruction::Nop(_) => {},
+            Instruction::Arith(op, r, val) => {
+                let lhs = self.registers[r.0];
+                let rhs = self.value(&val);
+                let result = match op {
+                    ArithOp::Set => Some(rhs),
+                    ArithOp::Add => lhs.checked_add(rhs),
+                    ArithOp::Sub => lhs.checked_sub(rhs),
+                    ArithOp::Mul => lhs.checked_mul(rhs),
+                    ArithOp::Div => lhs.checked_div(rhs),
+                    ArithOp::Mod => lhs.checked_rem(rhs),
+                };
+                self.registers[r.0] = result.ok_or(BootLoaderError::ArithmeticError(pointer))?;
+            },
+            Instruction::JumpIf(cond, r, val) => {
+                if cond.holds(self.registers[r.0]) {
+                    self.jump(pointer, val)?;
                 }
+            },
+            Instruction::Push(val) => {
+                self.stack.push(self.value(&val));
+                undo.stack = Some(StackChange::Pushed);
+            },
+            Instruction::Pop(r) => {
+                let val = self.stack.pop().ok_or(BootLoaderError::StackUnderflow(pointer))?;
+                self.registers[r.0] = val;
+                undo.stack = Some(StackChange::Popped(val));
+            },
+            Instruction::Call(val) => {
+                self.stack.push(pointer as i32 + 1);
+                undo.stack = Some(StackChange::Pushed);
+                self.jump(pointer, val)?;
+            },
+            Instruction::Ret => {
+                let address = self.stack.pop().ok_or(BootLoaderError::StackUnderflow(pointer))?;
+                undo.stack = Some(StackChange::Popped(address));
+                let offset = i32::try_from(address as i64 - pointer as i64)
+                    .map_err(|_| BootLoaderError::InvalidJump(pointer, address))?;
+                self.jump(pointer, offset)?;
+            },
+            Instruction::In(r) => {
+                let val = self.input.pop_front().ok_or(BootLoaderError::InputExhausted(pointer))?;
+                self.registers[r.0] = val;
+                self.inputs_read += 1;
+                undo.input = Some(val);
+            },
+            Instruction::Out(val) => {
+                self.output.push(self.value(&val));
+                undo.output = true;
+            },
+        }
+        Ok(())
+    }
+
+    // executed instructions ordered by address, with their hit counts
+    fn profile(&self) -> String {
+        let mut out = String::new();
+        let executed = self.hits.iter().filter(|&&hits| hits > 0).count();
+        writeln!(out, "{} of {} instructions executed", executed, self.program.len()).unwrap();
+        for (address, &hits) in self.hits.iter().enumerate().filter(|(_, &hits)| hits > 0) {
+            writeln!(out, "{:5} {:8} {}", address, hits, self.instruction_at(address).unwrap()).unwrap();
+        }
+        out
+    }
+
+    // undoes the last executed instruction, returning false if there is
+    // nothing to undo or history isn't being recorded
+    fn step_back(&mut self) -> bool {
+        if let Some(undo) = self.history.pop() {
+            self.revert(&undo);
+            self.hits[undo.pointer] -= 1;
+            match self.loop_detection {
+                LoopDetection::Revisit => self.instructions_called[undo.pointer] = false,
+                LoopDetection::State => { self.seen_states.remove(&self.machine_state()); },
             }
+            true
         } else {
-            Err(BootLoaderError::InstructionOverflow(self.pointer))
+            false
+        }
+    }
+
+    // restores the machine to how it was before the instruction `undo` was
+    // recorded for
+    fn revert(&mut self, undo: &Undo) {
+        match undo.stack {
+            Some(StackChange::Pushed) => { self.stack.pop(); },
+            Some(StackChange::Popped(val)) => self.stack.push(val),
+            None => {},
+        }
+        if let Some(val) = undo.input {
+            self.input.push_front(val);
+            self.inputs_read -= 1;
+        }
+        if undo.output {
+            self.output.pop();
+        }
+        self.pointer = undo.pointer;
+        self.registers = undo.registers;
+    }
+
+    // The complete machine state, including patches and the instructions
+    // already visited, as text. Undo history isn't included, so a restored
+    // bootloader can't step back past the point the snapshot was taken.
+    fn snapshot(&self) -> String {
+        fn join<T: ToString, I: IntoIterator<Item = T>>(vals: I) -> String {
+            vals.into_iter().map(|v| v.to_string()).collect::<Vec<String>>().join(" ")
+        }
+        let mut out = String::new();
+        writeln!(out, "{}", SNAPSHOT_HEADER).unwrap();
+        writeln!(out, "program {} {:016x}", self.program.len(), self.program.hash()).unwrap();
+        let loop_detection = match self.loop_detection {
+            LoopDetection::Revisit => "revisit",
+            LoopDetection::State => "state",
+        };
+        writeln!(out, "loop-detection {}", loop_detection).unwrap();
+        writeln!(out, "pointer {}", self.pointer).unwrap();
+        writeln!(out, "registers {}", join(self.registers)).unwrap();
+        writeln!(out, "stack {}", join(&self.stack)).unwrap();
+        writeln!(out, "input {}", join(&self.input)).unwrap();
+        writeln!(out, "inputs-read {}", self.inputs_read).unwrap();
+        writeln!(out, "output {}", join(&self.output)).unwrap();
+        let visited = self.instructions_called.iter().enumerate()
+            .filter(|(_, &called)| called)
+            .map(|(address, _)| address);
+        writeln!(out, "visited {}", join(visited)).unwrap();
+        writeln!(out, "hits {}", join(&self.hits)).unwrap();
+        for (address, instruction) in &self.patches {
+            writeln!(out, "patch {} {}", address, instruction).unwrap();
+        }
+        let mut states: Vec<&MachineState> = self.seen_states.iter().collect();
+        states.sort();
+        for state in states {
+            writeln!(
+                out, "state {} {} {} {}",
+                state.pointer, state.inputs_read, join(state.registers), join(&state.stack)
+            ).unwrap();
+        }
+        out
+    }
+
+    fn restore(program: &'a Program, snapshot: &str) -> Result<BootLoader<'a>, SnapshotError> {
+        let mut bootloader = BootLoader::new(program);
+        let mut lines = snapshot.lines().enumerate().map(|(line_no, line)| (line_no + 1, line));
+        match lines.next() {
+            Some((_, SNAPSHOT_HEADER)) => {},
+            Some((line_no, line)) => return Err(SnapshotError::Parse(line_no, line.to_string())),
+            None => return Err(SnapshotError::Parse(0, String::new())),
+        }
+        let mut checked_program = false;
+        for (line_no, line) in lines {
+            let err = || SnapshotError::Parse(line_no, line.to_string());
+            let (key, vals) = line.split_once(' ').unwrap_or((line, ""));
+            let numbers = || -> Result<Vec<i64>, SnapshotError> {
+                vals.split_whitespace().map(|v| v.parse::<i64>().map_err(|_| err())).collect()
+            };
+            let address = |val: i64| -> Result<usize, SnapshotError> {
+                if val >= 0 && (val as usize) < program.len() { Ok(val as usize) } else { Err(err()) }
+            };
+            match key {
+                "program" => {
+                    let (len, hash) = vals.split_once(' ').ok_or_else(err)?;
+                    if len != program.len().to_string() || hash != format!("{:016x}", program.hash()) {
+                        return Err(SnapshotError::ProgramMismatch);
+                    }
+                    checked_program = true;
+                },
+                "loop-detection" => {
+                    bootloader.loop_detection = match vals {
+                        "revisit" => LoopDetection::Revisit,
+                        "state" => LoopDetection::State,
+                        _ => return Err(err()),
+                    };
+                },
+                "pointer" => {
+                    bootloader.pointer = vals.parse::<usize>().ok()
+                        .filter(|&pointer| pointer <= program.len())
+                        .ok_or_else(err)?;
+                },
+                "registers" => {
+                    let registers = numbers()?;
+                    if registers.len() != bootloader.registers.len() {
+                        return Err(err());
+                    }
+                    for (register, val) in bootloader.registers.iter_mut().zip(registers) {
+                        *register = i32::try_from(val).map_err(|_| err())?;
+                    }
+                },
+                "stack" | "input" | "output" => {
+                    let vals = numbers()?.into_iter()
+                        .map(|val| i32::try_from(val).map_err(|_| err()))
+                        .collect::<Result<Vec<i32>, SnapshotError>>()?;
+                    match key {
+                        "stack" => bootloader.stack = vals,
+                        "input" => bootloader.input = vals.into(),
+                        _ => bootloader.output = vals,
+                    }
+                },
+                "inputs-read" => {
+                    bootloader.inputs_read = vals.parse::<usize>().map_err(|_| err())?;
+                },
+                "visited" => {
+                    for val in numbers()? {
+                        bootloader.instructions_called[address(val)?] = true;
+                    }
+                },
+                "hits" => {
+                    let hits = numbers()?;
+                    if hits.len() != program.len() || hits.iter().any(|&h| h < 0) {
+                        return Err(err());
+                    }
+                    bootloader.hits = hits.into_iter().map(|h| h as usize).collect();
+                },
+                "patch" => {
+                    let (address_val, instruction) = vals.split_once(' ').ok_or_else(err)?;
+                    let address = address(address_val.parse::<i64>().map_err(|_| err())?)?;
+                    let instruction = parse_instruction(line_no, instruction.to_string()).map_err(|_| err())?;
+                    bootloader.patches.insert(address, instruction);
+                },
+                "state" => {
+                    let vals = numbers()?;
+                    if vals.len() < 6 || vals[0] < 0 || vals[1] < 0 {
+                        return Err(err());
+                    }
+                    let to_i32 = |val: &i64| i32::try_from(*val).map_err(|_| err());
+                    let mut registers = [0; 4];
+                    for (register, val) in registers.iter_mut().zip(&vals[2..6]) {
+                        *register = to_i32(val)?;
+                    }
+                    bootloader.seen_states.insert(MachineState {
+                        pointer: vals[0] as usize,
+                        inputs_read: vals[1] as usize,
+                        registers,
+                        stack: vals[6..].iter().map(to_i32).collect::<Result<Vec<i32>, SnapshotError>>()?,
+                    });
+                },
+                _ => return Err(err()),
+            }
         }
+        if !checked_program {
+            return Err(SnapshotError::ProgramMismatch);
+        }
+        Ok(bootloader)
     }
 
     fn boot(&mut self) -> Result<i32, BootLoaderError> {
         loop {
             self.step()?;
-            if self.pointer == self.program.len() {
-                return Ok(self.acc);
+            if self.is_terminated() {
+                return Ok(self.acc());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Successor {
+    Block(usize),
+    Exit,
+    Invalid,
+    // wherever the address on top of the stack points
+    Return,
+}
+
+// a run of instructions that is only entered at `start` and only branches
+// after its last instruction
+#[derive(Debug, PartialEq)]
+struct BasicBlock {
+    start: usize,
+    end: usize,
+    successors: Vec<Successor>,
+}
+
+struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    fn new(program: &Program) -> ControlFlowGraph {
+        let len = program.len();
+        let mut leaders = vec![false; len];
+        if len > 0 {
+            leaders[0] = true;
+        }
+        for (address, instruction) in program.instructions.iter().enumerate() {
+            let target = match instruction {
+                Instruction::Nop(_) => None,
+                Instruction::Ret => Some(-1),
+                _ => instruction.jump_offset().map(|val| address as i64 + val as i64),
+            };
+            if let Some(target) = target {
+                if target >= 0 && (target as usize) < len {
+                    leaders[target as usize] = true;
+                }
+                if address + 1 < len {
+                    leaders[address + 1] = true;
+                }
+            }
+        }
+        let starts: Vec<usize> = (0..len).filter(|&address| leaders[address]).collect();
+        let successor = |target: i64| {
+            if target == len as i64 {
+                Successor::Exit
+            } else if target < 0 || target > len as i64 {
+                Successor::Invalid
+            } else {
+                Successor::Block(starts.binary_search(&(target as usize)).unwrap())
+            }
+        };
+        let blocks = starts.iter().enumerate().map(|(idx, &start)| {
+            let end = starts.get(idx + 1).copied().unwrap_or(len);
+            let last = end - 1;
+            let successors = match program.instructions[last] {
+                Instruction::Jmp(val) => vec![successor(last as i64 + val as i64)],
+                // a call eventually returns to the next instruction
+                Instruction::JumpIf(_, _, val) | Instruction::Call(val) => {
+                    vec![successor(last as i64 + val as i64), successor(end as i64)]
+                },
+                Instruction::Ret => vec![Successor::Return],
+                _ => vec![successor(end as i64)],
+            };
+            BasicBlock { start, end, successors }
+        }).collect();
+        ControlFlowGraph { blocks }
+    }
+
+    // renders the graph, shading the blocks that have been executed
+    // according to `hits`
+    fn to_dot(&self, program: &Program, hits: &[usize]) -> String {
+        let mut out = String::from("digraph program {\n    node [shape=box, fontname=\"monospace\"];\n");
+        out.push_str("    exit [shape=doublecircle];\n    invalid [shape=octagon];\n    ret [shape=circle];\n");
+        for (idx, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            let instructions = program.instructions[block.start..block.end].iter()
+                .zip(&hits[block.start..block.end]);
+            for (address, (instruction, hits)) in (block.start..).zip(instructions) {
+                write!(label, "{}: {} ({})\\l", address, instruction, hits).unwrap();
+            }
+            let style = if hits[block.start] > 0 { ", style=filled, fillcolor=lightgrey" } else { "" };
+            writeln!(out, "    b{} [label=\"{}\"{}];", idx, label, style).unwrap();
+        }
+        for (idx, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                match successor {
+                    Successor::Block(next) => writeln!(out, "    b{} -> b{};", idx, next).unwrap(),
+                    Successor::Exit => writeln!(out, "    b{} -> exit;", idx).unwrap(),
+                    Successor::Invalid => writeln!(out, "    b{} -> invalid;", idx).unwrap(),
+                    Successor::Return => writeln!(out, "    b{} -> ret [style=dashed];", idx).unwrap(),
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// the address execution continues at after the basic `instruction`, which
+// may be out of range
+fn next_address(address: usize, instruction: &Instruction) -> i64 {
+    match instruction {
+        Instruction::Jmp(val) => address as i64 + *val as i64,
+        _ => address as i64 + 1,
+    }
+}
+
+// for every address, and for the end of the program at `program.len()`,
+// whether the unmodified program runs to completion when started there
+fn terminating_addresses(program: &Program) -> Vec<bool> {
+    let len = program.len();
+    let mut predecessors = vec![vec![]; len + 1];
+    for (address, instruction) in program.instructions.iter().enumerate() {
+        let next = next_address(address, instruction);
+        if next >= 0 && next <= len as i64 {
+            predecessors[next as usize].push(address);
+        }
+    }
+    let mut terminates = vec![false; len + 1];
+    terminates[len] = true;
+    let mut stack = vec![len];
+    while let Some(address) = stack.pop() {
+        for &prev in &predecessors[address] {
+            if !terminates[prev] {
+                terminates[prev] = true;
+                stack.push(prev);
+            }
+        }
+    }
+    terminates
+}
+
+// Addresses of every jmp/nop on the program's execution path whose flip
+// sends execution somewhere that runs to completion. Each instruction on the
+// path runs once before the loop, so flipping one of these makes the whole
+// program terminate. Empty if the program already terminates, or if it uses
+// the extended instructions, whose control flow depends on runtime state.
+fn find_fixes(program: &Program) -> Vec<usize> {
+    if !program.is_basic() {
+        return vec![];
+    }
+    let len = program.len();
+    let terminates = terminating_addresses(program);
+    let mut visited = vec![false; len];
+    let mut fixes = vec![];
+    if terminates[0] {
+        return fixes;
+    }
+    let mut address = 0;
+    while address >= 0 && address < len as i64 && !visited[address as usize] {
+        let current = address as usize;
+        let instruction = &program.instructions[current];
+        visited[current] = true;
+        let flipped = match instruction {
+            Instruction::Jmp(_) => Some(current as i64 + 1),
+            Instruction::Nop(val) => Some(current as i64 + *val as i64),
+            _ => None,
+        };
+        if let Some(target) = flipped.filter(|&t| t >= 0 && t <= len as i64) {
+            if terminates[target as usize] {
+                fixes.push(current);
+            }
+        }
+        address = next_address(current, instruction);
+    }
+    fixes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Term {
+    Register(Register),
+    Pointer,
+    Value(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// a debugger expression: a register, `pc` or a number, optionally combined
+// with a second term, e.g. `acc > 10` or `pc + 1`
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    text: String,
+    lhs: Term,
+    rhs: Option<(Op, Term)>,
+}
+
+impl Expr {
+    fn parse(text: &str) -> Result<Expr, String> {
+        let mut tokens = vec![];
+        let mut chars = text.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c.is_ascii_alphanumeric() {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            } else if c == '+' || c == '-' {
+                tokens.push(c.to_string());
+                chars.next();
+            } else {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek().filter(|c| "=!<>".contains(**c)) {
+                    token.push(c);
+                    chars.next();
+                }
+                if token.is_empty() {
+                    return Err(format!("unexpected character: {}", c));
+                }
+                tokens.push(token);
+            }
+        }
+        let term = |token: &str| match token {
+            "pc" | "pointer" => Ok(Term::Pointer),
+            _ => Register::parse(token).map(Term::Register)
+                .or_else(|| token.parse::<i64>().ok().map(Term::Value))
+                .ok_or_else(|| format!("unknown term: {}", token)),
+        };
+        let op = |token: &str| match token {
+            "+" => Ok(Op::Add),
+            "-" => Ok(Op::Sub),
+            "==" => Ok(Op::Eq),
+            "!=" => Ok(Op::Ne),
+            "<" => Ok(Op::Lt),
+            "<=" => Ok(Op::Le),
+            ">" => Ok(Op::Gt),
+            ">=" => Ok(Op::Ge),
+            _ => Err(format!("unknown operator: {}", token)),
+        };
+        let tokens: Vec<&str> = tokens.iter().map(|t| t.as_str()).collect();
+        let (lhs, rhs) = match tokens.as_slice() {
+            [lhs] => (term(lhs)?, None),
+            [lhs, o, rhs] => (term(lhs)?, Some((op(o)?, term(rhs)?))),
+            // a leading sign on a number, e.g. `acc > -5`
+            [lhs, o, sign, rhs] if *sign == "-" => {
+                let rhs = match term(rhs)? {
+                    Term::Value(val) => Term::Value(-val),
+                    _ => return Err(format!("unexpected sign: {}", text)),
+                };
+                (term(lhs)?, Some((op(o)?, rhs)))
+            },
+            _ => return Err(format!("invalid expression: {}", text)),
+        };
+        Ok(Expr { text: tokens.join(" "), lhs, rhs })
+    }
+
+    fn evaluate(&self, bootloader: &BootLoader) -> i64 {
+        let value = |term: Term| match term {
+            Term::Register(r) => bootloader.registers[r.0] as i64,
+            Term::Pointer => bootloader.pointer as i64,
+            Term::Value(val) => val,
+        };
+        let lhs = value(self.lhs);
+        match self.rhs {
+            None => lhs,
+            Some((op, rhs)) => {
+                let rhs = value(rhs);
+                match op {
+                    // wraps rather than panicking the debugger on whatever
+                    // the user types
+                    Op::Add => lhs.wrapping_add(rhs),
+                    Op::Sub => lhs.wrapping_sub(rhs),
+                    Op::Eq => (lhs == rhs) as i64,
+                    Op::Ne => (lhs != rhs) as i64,
+                    Op::Lt => (lhs < rhs) as i64,
+                    Op::Le => (lhs <= rhs) as i64,
+                    Op::Gt => (lhs > rhs) as i64,
+                    Op::Ge => (lhs >= rhs) as i64,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Breakpoint {
+    Address(usize),
+    Condition(Expr),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(address) => write!(f, "at {}", address),
+            Breakpoint::Condition(expr) => write!(f, "if {}", expr.text),
+        }
+    }
+}
+
+enum Stop {
+    Breakpoint(usize),
+    Address,
+    Terminated,
+    Error(BootLoaderError),
+}
+
+const DEBUGGER_HELP: &str = "\
+step [n]            execute n instructions (default 1)
+back [n]            undo n instructions (default 1)
+continue            run until a breakpoint, termination or error
+until <addr>        run until the pointer reaches addr
+break <addr>        break before executing addr
+break if <expr>     break when expr is non-zero, e.g. `break if acc > 10`
+delete <n>          remove breakpoint n
+watch <expr>        print expr whenever execution stops
+unwatch <n>         remove watch n
+info                list breakpoints and watches
+regs                print the pointer, registers and stack
+list [n]            print n instructions either side of the pointer
+save <file>         write a snapshot of the machine state to file
+load <file>         restore the machine state from a snapshot file
+quit                exit the debugger";
+
+struct Debugger<'a> {
+    bootloader: BootLoader<'a>,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Expr>,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(program: &'a Program) -> Debugger<'a> {
+        let loop_detection = if program.is_basic() {
+            LoopDetection::Revisit
+        } else {
+            LoopDetection::State
+        };
+        Debugger {
+            bootloader: BootLoader::new(program).with_loop_detection(loop_detection).with_history(),
+            breakpoints: vec![],
+            watches: vec![],
+        }
+    }
+
+    fn run<R: BufRead, W: io::Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        write!(out, "(dbg) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.execute(line?.trim(), &mut out)? {
+                break;
+            }
+            write!(out, "(dbg) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    fn breakpoint_hit(&self) -> Option<usize> {
+        self.breakpoints.iter().position(|bp| match bp {
+            Breakpoint::Address(address) => *address == self.bootloader.pointer,
+            Breakpoint::Condition(expr) => expr.evaluate(&self.bootloader) != 0,
+        })
+    }
+
+    // executes up to `limit` instructions, stopping early at breakpoints
+    // (other than one at the starting pointer), at `until`, on termination
+    // or on error
+    fn resume(&mut self, limit: Option<usize>, until: Option<usize>) -> Option<Stop> {
+        let mut executed = 0;
+        loop {
+            if self.bootloader.is_terminated() {
+                return Some(Stop::Terminated);
+            }
+            if limit == Some(executed) {
+                return None;
+            }
+            if executed > 0 {
+                if until == Some(self.bootloader.pointer) {
+                    return Some(Stop::Address);
+                }
+                if let Some(idx) = self.breakpoint_hit() {
+                    return Some(Stop::Breakpoint(idx));
+                }
+            }
+            if let Err(e) = self.bootloader.step() {
+                return Some(Stop::Error(e));
+            }
+            executed += 1;
+        }
+    }
+
+    fn report<W: io::Write>(&self, stop: Option<Stop>, out: &mut W) -> io::Result<()> {
+        match stop {
+            Some(Stop::Breakpoint(idx)) => {
+                writeln!(out, "breakpoint {} ({}) hit", idx, self.breakpoints[idx])?
+            },
+            Some(Stop::Address) | None => {},
+            Some(Stop::Terminated) => writeln!(out, "program terminated")?,
+            Some(Stop::Error(e)) => writeln!(out, "stopped: {:?}", e)?,
+        }
+        self.print_location(out)?;
+        for (idx, watch) in self.watches.iter().enumerate() {
+            writeln!(out, "watch {}: {} = {}", idx, watch.text, watch.evaluate(&self.bootloader))?;
+        }
+        Ok(())
+    }
+
+    fn print_location<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let bl = &self.bootloader;
+        write!(out, "pc={} acc={}", bl.pointer, bl.acc())?;
+        if !bl.program.is_basic() {
+            for (name, val) in REGISTER_NAMES.iter().zip(&bl.registers).skip(1) {
+                write!(out, " {}={}", name, val)?;
+            }
+            write!(out, " stack={:?}", bl.stack)?;
+        }
+        match bl.instruction_at(bl.pointer) {
+            Some(instruction) => writeln!(out, " | {}", instruction),
+            None => writeln!(out, " | <end>"),
+        }
+    }
+
+    fn list<W: io::Write>(&self, context: usize, out: &mut W) -> io::Result<()> {
+        let bl = &self.bootloader;
+        let start = bl.pointer.saturating_sub(context);
+        let end = (bl.pointer + context + 1).min(bl.program.len());
+        for address in start..end {
+            let marker = if address == bl.pointer { '>' } else { ' ' };
+            let visited = if bl.instructions_called[address] { '*' } else { ' ' };
+            let label = bl.program.labels.get(&address)
+                .map(|names| names.iter().map(|l| format!("{}:", l)).collect::<Vec<String>>().join(" "))
+                .unwrap_or_default();
+            writeln!(
+                out, "{}{} {:5} {:10} {}",
+                marker, visited, address, label, bl.instruction_at(address).unwrap()
+            )?;
+        }
+        Ok(())
+    }
+
+    // returns false when the debugger should exit
+    fn execute<W: io::Write>(&mut self, command: &str, out: &mut W) -> io::Result<bool> {
+        let (cmd, args) = match command.find(char::is_whitespace) {
+            Some(idx) => (&command[..idx], command[idx..].trim()),
+            None => (command, ""),
+        };
+        if cmd == "q" || cmd == "quit" {
+            return Ok(false);
+        }
+        match self.command(cmd, args, out) {
+            Ok(()) => {},
+            Err(DebuggerError::Io(e)) => return Err(e),
+            Err(DebuggerError::Usage(e)) => writeln!(out, "error: {}", e)?,
+        }
+        Ok(true)
+    }
+
+    fn command<W: io::Write>(&mut self, cmd: &str, args: &str, out: &mut W) -> Result<(), DebuggerError> {
+        let number = |default: usize| -> Result<usize, DebuggerError> {
+            if args.is_empty() {
+                Ok(default)
+            } else {
+                args.parse::<usize>()
+                    .map_err(|_| DebuggerError::Usage(format!("expected a number, got: {}", args)))
             }
+        };
+        match cmd {
+            "" => {},
+            "s" | "step" => {
+                let stop = self.resume(Some(number(1)?), None);
+                self.report(stop, out)?;
+            },
+            "b" | "back" => {
+                let n = number(1)?;
+                let undone = (0..n).take_while(|_| self.bootloader.step_back()).count();
+                if undone < n {
+                    writeln!(out, "reached start of history")?;
+                }
+                self.report(None, out)?;
+            },
+            "c" | "continue" => {
+                let stop = self.resume(None, None);
+                self.report(stop, out)?;
+            },
+            "u" | "until" => {
+                let address = args.parse::<usize>()
+                    .map_err(|_| DebuggerError::Usage(format!("expected an address, got: {}", args)))?;
+                let stop = self.resume(None, Some(address));
+                self.report(stop, out)?;
+            },
+            "break" => {
+                let bp = if let Some(cond) = args.strip_prefix("if ") {
+                    Breakpoint::Condition(Expr::parse(cond).map_err(DebuggerError::Usage)?)
+                } else {
+                    Breakpoint::Address(args.parse::<usize>().map_err(|_| {
+                        DebuggerError::Usage(format!("expected an address or `if <expr>`, got: {}", args))
+                    })?)
+                };
+                writeln!(out, "breakpoint {} {}", self.breakpoints.len(), bp)?;
+                self.breakpoints.push(bp);
+            },
+            "delete" => {
+                let idx = number(usize::MAX).ok().filter(|&idx| idx < self.breakpoints.len())
+                    .ok_or_else(|| DebuggerError::Usage(format!("no breakpoint: {}", args)))?;
+                self.breakpoints.remove(idx);
+            },
+            "watch" => {
+                let expr = Expr::parse(args).map_err(DebuggerError::Usage)?;
+                writeln!(out, "watch {}: {} = {}", self.watches.len(), expr.text, expr.evaluate(&self.bootloader))?;
+                self.watches.push(expr);
+            },
+            "unwatch" => {
+                let idx = number(usize::MAX).ok().filter(|&idx| idx < self.watches.len())
+                    .ok_or_else(|| DebuggerError::Usage(format!("no watch: {}", args)))?;
+                self.watches.remove(idx);
+            },
+            "info" => {
+                for (idx, bp) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "breakpoint {} {}", idx, bp)?;
+                }
+                for (idx, watch) in self.watches.iter().enumerate() {
+                    writeln!(out, "watch {}: {}", idx, watch.text)?;
+                }
+            },
+            "r" | "regs" => self.print_location(out)?,
+            "save" | "load" if args.is_empty() => {
+                return Err(DebuggerError::Usage(format!("expected a file name: {} <file>", cmd)));
+            },
+            "save" => {
+                fs::write(args, self.bootloader.snapshot())
+                    .map_err(|e| DebuggerError::Usage(format!("{}: {}", args, e)))?;
+                writeln!(out, "saved snapshot to {}", args)?;
+            },
+            "load" => {
+                let snapshot = fs::read_to_string(args)
+                    .map_err(|e| DebuggerError::Usage(format!("{}: {}", args, e)))?;
+                self.bootloader = BootLoader::restore(self.bootloader.program, &snapshot)
+                    .map_err(|e| DebuggerError::Usage(e.to_string()))?
+                    .with_history();
+                self.report(None, out)?;
+            },
+            "l" | "list" => self.list(number(3)?, out)?,
+            "h" | "help" => writeln!(out, "{}", DEBUGGER_HELP)?,
+            _ => return Err(DebuggerError::Usage(format!("unknown command: {} (try `help`)", cmd))),
         }
+        Ok(())
+    }
+}
+
+enum DebuggerError {
+    Io(io::Error),
+    Usage(String),
+}
+
+impl From<io::Error> for DebuggerError {
+    fn from(e: io::Error) -> DebuggerError {
+        DebuggerError::Io(e)
     }
 }
 
@@ -135,32 +1500,99 @@ fn part1(program: &Program) -> i32 {
     }
 }
 
-fn part2(mut program: Program) -> i32 {
-    let mut idx = 0;
-    while idx < program.len() {
-        if let Ok(_) = program.swap_instruction(idx) {
-            let mut bootloader = BootLoader::new(&program);
-            match bootloader.boot() {
-                Ok(result) => {
-                    return result;
-                },
-                Err(BootLoaderError::LoopDetected(_, _)) => {},
-                Err(e) => {
-                    panic!("{:?}", e);
-                }
-            };
-            program.swap_instruction(idx).unwrap();
-        }
-        idx += 1;
+fn part2(program: &Program) -> i32 {
+    let fixes = find_fixes(program);
+    let address = *fixes.first().expect("no single instruction flip repairs the program");
+    let flipped = program.instructions[address].flipped().unwrap();
+    match BootLoader::new(program).with_patch(address, flipped).boot() {
+        Ok(result) => result,
+        Err(e) => panic!("{:?}", e),
     }
-    panic!("Got to end of program with no match");
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(|s| s.as_str()).collect::<Vec<&str>>().as_slice() {
+        ["asm", filename] => {
+            let program = assemble(&fs::read_to_string(filename)?)?;
+            for instruction in program.instructions {
+                println!("{}", instruction);
+            }
+            return Ok(());
+        },
+        ["run", filename, inputs @ ..] => {
+            let program = assemble(&fs::read_to_string(filename)?)?;
+            let inputs = inputs.iter()
+                .map(|i| i.parse::<i32>())
+                .collect::<Result<Vec<i32>, _>>()?;
+            let mut bootloader = BootLoader::new(&program)
+                .with_loop_detection(LoopDetection::State)
+                .with_input(inputs);
+            let result = bootloader.boot();
+            for val in &bootloader.output {
+                println!("{}", val);
+            }
+            println!("acc: {}", result?);
+            return Ok(());
+        },
+        ["disasm"] | ["disasm", _] => {
+            let filename = args.get(1).map(|s| s.as_str()).unwrap_or("day_08_input.txt");
+            let program: Program = parse_input_file(filename, parse_instruction)?;
+            print!("{}", disassemble(&program));
+            return Ok(());
+        },
+        ["trace", trace_file] | ["trace", trace_file, _] => {
+            let filename = args.get(2).map(|s| s.as_str()).unwrap_or("day_08_input.txt");
+            let program: Program = parse_input_file(filename, parse_instruction)?;
+            let trace = io::BufWriter::new(fs::File::create(trace_file)?);
+            let mut bootloader = BootLoader::new(&program).with_trace(trace)?;
+            println!("{:?}", bootloader.boot());
+            print!("{}", bootloader.profile());
+            return Ok(());
+        },
+        ["cfg"] | ["cfg", _] => {
+            let filename = args.get(1).map(|s| s.as_str()).unwrap_or("day_08_input.txt");
+            let program: Program = parse_input_file(filename, parse_instruction)?;
+            let mut bootloader = BootLoader::new(&program);
+            let _ = bootloader.boot();
+            print!("{}", ControlFlowGraph::new(&program).to_dot(&program, &bootloader.hits));
+            return Ok(());
+        },
+        ["repair"] | ["repair", _] => {
+            let filename = args.get(1).map(|s| s.as_str()).unwrap_or("day_08_input.txt");
+            let program: Program = parse_input_file(filename, parse_instruction)?;
+            for address in find_fixes(&program) {
+                let original = program.instructions[address];
+                let flipped = original.flipped().unwrap();
+                let result = BootLoader::new(&program).with_patch(address, flipped).boot();
+                println!("{}: {} -> {}, {:?}", address, original, flipped, result);
+            }
+            return Ok(());
+        },
+        ["resume", snapshot] | ["resume", snapshot, _] => {
+            let filename = args.get(2).map(|s| s.as_str()).unwrap_or("day_08_input.txt");
+            let program: Program = parse_input_file(filename, parse_instruction)?;
+            let mut bootloader = BootLoader::restore(&program, &fs::read_to_string(snapshot)?)?;
+            let result = bootloader.boot();
+            for val in &bootloader.output {
+                println!("{}", val);
+            }
+            println!("{:?}", result);
+            return Ok(());
+        },
+        ["debug"] | ["debug", _] => {
+            let filename = args.get(1).map(|s| s.as_str()).unwrap_or("day_08_input.txt");
+            let program: Program = parse_input_file(filename, parse_instruction)?;
+            let stdin = io::stdin();
+            Debugger::new(&program).run(stdin.lock(), io::stdout())?;
+            return Ok(());
+        },
+        _ => {}
+    }
     let program: Program = parse_input_file("day_08_input.txt", parse_instruction)?;
     let result = part1(&program);
     println!("part1: {}", result);
-    let result = part2(program);
+    let result = part2(&program);
     println!("part2: {}", result);
     Ok(())
 }
@@ -172,9 +1604,7 @@ mod test {
 
     use adventofcode2020::parse_input_lines;
 
-    #[test]
-    fn test_1() -> Result<(), Box<dyn std::error::Error>> {
-        let input = r#"nop +0
+    const EXAMPLE: &str = r#"nop +0
 acc +1
 jmp +4
 acc +3
@@ -184,13 +1614,318 @@ acc +1
 jmp -4
 acc +6"#;
 
-        let program: Program = parse_input_lines(input.as_bytes(), parse_instruction)?;
+    #[test]
+    fn test_1() -> Result<(), Box<dyn std::error::Error>> {
+        let program: Program = parse_input_lines(EXAMPLE.as_bytes(), parse_instruction)?;
         let result = part1(&program);
         assert_eq!(result, 5);
 
-        let result = part2(program);
+        let result = part2(&program);
         assert_eq!(result, 8);
 
         Ok(())
     }
+
+    #[test]
+    fn test_assembler() -> Result<(), Box<dyn std::error::Error>> {
+        let source = r#"
+; boot code from the puzzle example
+.equ step 1
+start:  nop +0
+inc:    acc step        # increment
+        jmp skip
+back:   acc +3
+        jmp inc
+        acc -99
+skip:   acc +1
+        jmp back
+        acc +6
+.fill 2 nop +0
+end:
+"#;
+        let program = assemble(source)?;
+        let example: Program = parse_input_lines(EXAMPLE.as_bytes(), parse_instruction)?;
+        assert_eq!(program.instructions[..9], example.instructions[..]);
+        assert_eq!(program.len(), 11);
+        assert_eq!(program.labels[&11], vec!["end"]);
+
+        let text = disassemble(&program);
+        assert!(text.starts_with("start:\n    nop +0\ninc:\n    acc +1\n    jmp skip\nback:\n"));
+        assert!(text.contains("    jmp inc\n"));
+        assert!(text.ends_with("end:\n"));
+        let reassembled = assemble(&text)?;
+        assert_eq!(reassembled.instructions, program.instructions);
+
+        let text = disassemble(&example);
+        assert_eq!(assemble(&text)?.instructions, example.instructions);
+        assert!(text.contains("L6:\n    acc +1\n    jmp L3\n"));
+
+        // every label sharing an address survives, and generated names
+        // avoid the program's own
+        let program = assemble("L2: top: jmp +2\n    nop +0\n    jmp top\n")?;
+        assert_eq!(program.labels[&0], vec!["L2", "top"]);
+        let text = disassemble(&program);
+        assert_eq!(text, "L2:\ntop:\n    jmp L2_1\n    nop +0\nL2_1:\n    jmp L2\n");
+        assert_eq!(assemble(&text)?.instructions, program.instructions);
+
+        assert_eq!(assemble("jmp nowhere").err(), Some(AsmError::UndefinedSymbol(1, "nowhere".to_string())));
+        assert_eq!(assemble("a: nop +0\na: nop +0").err(), Some(AsmError::DuplicateSymbol(2, "a".to_string())));
+        assert_eq!(assemble("\nfrob +2").err(), Some(AsmError::UnknownOpcode(2, "frob".to_string())));
+        assert_eq!(assemble("mul +2").err(), Some(AsmError::Syntax(1, "mul".to_string())));
+        assert_eq!(assemble(".org 5").err(), Some(AsmError::UnknownDirective(1, ".org".to_string())));
+        assert!(parse_instruction(1, "acc".to_string()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debugger() -> Result<(), Box<dyn std::error::Error>> {
+        let program = assemble(EXAMPLE)?;
+        let script = r#"break 4
+watch acc + 100
+continue
+step 2
+back 3
+regs
+break if acc >= 5
+delete 0
+continue
+list 1
+save
+load /nonexistent/snapshot.txt
+frobnicate
+quit
+step
+"#;
+        let mut out = vec![];
+        Debugger::new(&program).run(script.as_bytes(), &mut out)?;
+        let out = String::from_utf8(out)?;
+        let expected = r#"(dbg) breakpoint 0 at 4
+(dbg) watch 0: acc + 100 = 100
+(dbg) breakpoint 0 (at 4) hit
+pc=4 acc=5 | jmp -3
+watch 0: acc + 100 = 105
+(dbg) stopped: LoopDetected(1, 5)
+pc=1 acc=5 | acc +1
+watch 0: acc + 100 = 105
+(dbg) pc=7 acc=2 | jmp -4
+watch 0: acc + 100 = 102
+(dbg) pc=7 acc=2 | jmp -4
+(dbg) breakpoint 1 if acc >= 5
+(dbg) (dbg) breakpoint 0 (if acc >= 5) hit
+pc=4 acc=5 | jmp -3
+watch 0: acc + 100 = 105
+(dbg)  *     3            acc +3
+>      4            jmp -3
+       5            acc -99
+(dbg) error: expected a file name: save <file>
+(dbg) error: /nonexistent/snapshot.txt: No such file or directory (os error 2)
+(dbg) error: unknown command: frobnicate (try `help`)
+(dbg) 
+"#;
+        assert_eq!(out, expected);
+
+        let mut out = vec![];
+        let script = "watch pc + 9223372036854775807\nwatch pc - -9223372036854775807\nstep\n";
+        Debugger::new(&program).run(script.as_bytes(), &mut out)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("watch 0: pc + 9223372036854775807 = 9223372036854775807\n"));
+        assert!(out.contains("watch 0: pc + 9223372036854775807 = -9223372036854775808\n"));
+        assert!(out.contains("watch 1: pc - - 9223372036854775807 = -9223372036854775808\n"));
+
+        assert_eq!(Expr::parse("acc>-5")?.rhs, Some((Op::Gt, Term::Value(-5))));
+        assert!(Expr::parse("acc * 2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_and_profile() -> Result<(), Box<dyn std::error::Error>> {
+        let program = assemble(EXAMPLE)?;
+        let mut trace = vec![];
+        let mut bootloader = BootLoader::new(&program).with_trace(&mut trace)?;
+        assert!(matches!(bootloader.boot(), Err(BootLoaderError::LoopDetected(1, 5))));
+        assert_eq!(bootloader.hits, vec![1, 1, 1, 1, 1, 0, 1, 1, 0]);
+        assert!(bootloader.profile().starts_with("7 of 9 instructions executed\n    0        1 nop +0\n"));
+        drop(bootloader);
+        let trace = String::from_utf8(trace)?;
+        assert_eq!(trace.lines().count(), 8);
+        assert_eq!(trace.lines().nth(4), Some("6,acc +1,1,2"));
+
+        let cfg = ControlFlowGraph::new(&program);
+        let blocks: Vec<(usize, usize, &[Successor])> = cfg.blocks.iter()
+            .map(|b| (b.start, b.end, b.successors.as_slice()))
+            .collect();
+        assert_eq!(blocks, vec![
+            (0, 1, &[Successor::Block(1)][..]),
+            (1, 3, &[Successor::Block(4)][..]),
+            (3, 5, &[Successor::Block(1)][..]),
+            (5, 6, &[Successor::Block(4)][..]),
+            (6, 8, &[Successor::Block(2)][..]),
+            (8, 9, &[Successor::Exit][..]),
+        ]);
+        let dot = cfg.to_dot(&program, &[1, 1, 1, 1, 1, 0, 1, 1, 0]);
+        assert!(dot.contains("    b1 [label=\"1: acc +1 (1)\\l2: jmp +4 (1)\\l\", style=filled, fillcolor=lightgrey];\n"));
+        assert!(dot.contains("    b3 [label=\"5: acc -99 (0)\\l\"];\n"));
+        assert!(dot.contains("    b5 -> exit;\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_fixes() -> Result<(), Box<dyn std::error::Error>> {
+        let program = assemble(EXAMPLE)?;
+        assert_eq!(find_fixes(&program), vec![7]);
+        assert_eq!(
+            terminating_addresses(&program),
+            vec![false, false, false, false, false, false, false, false, true, true]
+        );
+
+        let program = assemble("nop +3\njmp +0\njmp +1")?;
+        assert_eq!(find_fixes(&program), vec![0, 1]);
+
+        let program = assemble("acc +1\nnop +0")?;
+        assert!(find_fixes(&program).is_empty());
+        let program = assemble("acc +1\njmp -1")?;
+        assert_eq!(find_fixes(&program), vec![1]);
+        let program = assemble("acc +1\njmp -1\nacc +2\njmp -1")?;
+        assert!(find_fixes(&program).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_instructions() -> Result<(), Box<dyn std::error::Error>> {
+        let source = r#"
+; reads numbers until a zero, writing each one's factorial and squaring the
+; running total in acc
+next:   in b
+        jz b done
+        set c 1
+fact:   mul c b
+        sub b 1
+        jgz b fact
+        out c
+        push c
+        call square
+        add acc c
+        jmp next
+square: pop d               ; the return address
+        pop c
+        mul c c
+        push d
+        ret
+done:   out acc
+"#;
+        let program = assemble(source)?;
+        assert!(!program.is_basic());
+        let text = disassemble(&program);
+        assert!(text.contains("    jgz b fact\n"));
+        assert!(text.contains("    call square\n"));
+        assert!(text.contains("    mul c c\n"));
+        assert_eq!(assemble(&text)?.instructions, program.instructions);
+
+        let mut bootloader = BootLoader::new(&program)
+            .with_loop_detection(LoopDetection::State)
+            .with_input(vec![3, 4, 0])
+            .with_history();
+        assert_eq!(bootloader.boot()?, 36 + 576);
+        assert_eq!(bootloader.output, vec![6, 24, 612]);
+        while bootloader.step_back() {}
+        assert_eq!(bootloader.pointer, 0);
+        assert_eq!(bootloader.registers, [0; 4]);
+        assert!(bootloader.output.is_empty());
+        assert_eq!(bootloader.input, vec![3, 4, 0]);
+        assert_eq!(bootloader.boot()?, 612);
+
+        // revisiting instructions is a loop for the original instruction set
+        let mut bootloader = BootLoader::new(&program).with_input(vec![3, 0]);
+        assert!(matches!(bootloader.boot(), Err(BootLoaderError::LoopDetected(3, 0))));
+        // only the debugger keeps undo history
+        assert!(bootloader.history.is_empty());
+        assert!(!bootloader.step_back());
+
+        let mut bootloader = BootLoader::new(&program).with_loop_detection(LoopDetection::State);
+        assert!(matches!(bootloader.boot(), Err(BootLoaderError::InputExhausted(0))));
+
+        let program = assemble("set b 1\nloop: jnz b loop")?;
+        let mut bootloader = BootLoader::new(&program).with_loop_detection(LoopDetection::State);
+        assert!(matches!(bootloader.boot(), Err(BootLoaderError::LoopDetected(1, 0))));
+
+        let program = assemble("div acc b")?;
+        assert!(matches!(BootLoader::new(&program).boot(), Err(BootLoaderError::ArithmeticError(0))));
+        let program = assemble("ret")?;
+        assert!(matches!(BootLoader::new(&program).boot(), Err(BootLoaderError::StackUnderflow(0))));
+
+        assert_eq!(parse_instruction(1, "add b -3".to_string())?, Instruction::Arith(ArithOp::Add, Register(1), Value::Immediate(-3)));
+        assert!(parse_instruction(1, "add +3 b".to_string()).is_err());
+        assert_eq!(assemble("push b c").err(), Some(AsmError::Syntax(1, "push".to_string())));
+        assert_eq!(assemble("acc: nop +0").err(), Some(AsmError::Syntax(1, "acc".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failing_instructions() -> Result<(), Box<dyn std::error::Error>> {
+        let program = assemble("nop +0\nret")?;
+        let mut bootloader = BootLoader::new(&program).with_history();
+        bootloader.step()?;
+        for _ in 0..2 {
+            assert!(matches!(bootloader.step(), Err(BootLoaderError::StackUnderflow(1))));
+        }
+        assert_eq!(bootloader.pointer, 1);
+        assert_eq!(bootloader.hits, vec![1, 0]);
+        assert!(!bootloader.instructions_called[1]);
+        assert!(bootloader.step_back());
+        assert!(!bootloader.step_back());
+
+        let program = assemble("push -5\nret")?;
+        let mut bootloader = BootLoader::new(&program);
+        assert!(matches!(bootloader.boot(), Err(BootLoaderError::InvalidJump(1, _))));
+        assert_eq!(bootloader.stack, vec![-5]);
+
+        let program = assemble("set acc 2147483647\nacc +1")?;
+        let mut bootloader = BootLoader::new(&program);
+        assert!(matches!(bootloader.boot(), Err(BootLoaderError::ArithmeticError(1))));
+        assert_eq!(bootloader.acc(), i32::MAX);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+        let program = assemble(EXAMPLE)?;
+        let mut bootloader = BootLoader::new(&program).with_patch(7, Instruction::Nop(-4));
+        for _ in 0..4 {
+            bootloader.step()?;
+        }
+        let snapshot = bootloader.snapshot();
+        assert!(snapshot.contains("\nvisited 0 1 2 6\n"));
+        assert!(snapshot.contains("\npatch 7 nop -4\n"));
+
+        let mut restored = BootLoader::restore(&program, &snapshot)?;
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.boot()?, 8);
+        assert_eq!(bootloader.boot()?, 8);
+        assert_eq!(restored.snapshot(), bootloader.snapshot());
+
+        let mut restored = BootLoader::restore(&program, &snapshot)?;
+        restored.patches.clear();
+        assert!(matches!(restored.boot(), Err(BootLoaderError::LoopDetected(1, 5))));
+
+        let program = assemble("in b\npush b\ncall +2\njmp +3\npop c\npop d\nout d")?;
+        let mut bootloader = BootLoader::new(&program)
+            .with_loop_detection(LoopDetection::State)
+            .with_input(vec![7, 8]);
+        for _ in 0..3 {
+            bootloader.step()?;
+        }
+        let snapshot = bootloader.snapshot();
+        assert!(snapshot.contains("\nstack 7 3\n"));
+        assert!(snapshot.contains("\nstate 2 1 0 7 0 0 7\n"));
+        let mut restored = BootLoader::restore(&program, &snapshot)?;
+        assert_eq!(restored.input, vec![8]);
+        assert_eq!(restored.boot()?, bootloader.boot()?);
+        assert_eq!(restored.output, vec![7]);
+
+        let other = assemble("nop +0")?;
+        assert!(matches!(BootLoader::restore(&other, &snapshot), Err(SnapshotError::ProgramMismatch)));
+        let corrupt = snapshot.replace("\npointer 4\n", "\npointer 40\n");
+        assert!(matches!(BootLoader::restore(&program, &corrupt), Err(SnapshotError::Parse(4, _))));
+        Ok(())
+    }
 }
@@ -1,46 +1,156 @@
-use adventofcode2020::read_input_lines;
+use std::{env, io::{self, BufRead}, hash::Hash, str::FromStr};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use adventofcode2020::{ReadError, read_input_lines};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|a| a.as_str()) == Some("stream") {
+        // e.g. `cat numbers.txt | cargo run --bin day_09 -- stream 25`
+        let preamble = args.get(1).map(|p| p.parse::<usize>()).transpose()?.unwrap_or(25);
+        let stdin = io::stdin();
+        for invalid in stream_invalid_numbers::<u128, _>(stdin.lock(), preamble) {
+            let (line_no, n) = invalid?;
+            println!("line {}: {}", line_no, n);
+        }
+        return Ok(());
+    }
     let input = read_input_lines("day_09_input.txt")?;
-    let result = part1(&input, 25);
+    let result = part1(&input, 25).unwrap();
     println!("part1: {}", result);
-    let result = part2(&input, result);
+    let result = part2(&input, result).unwrap();
     println!("part2: {}", result);
     Ok(())
 }
 
-fn part1(input: &[u64], preamble: usize) -> u64 {
-    let choices = &input[..preamble];
-    let target = input[preamble];
-    let m = choices.iter().enumerate().any(|(i, a)| {
-        choices.iter().enumerate().any(|(j, b)| {
-            i != j && a + b == target
-        })
-    });
-    if m {
-        part1(&input[1..], preamble)
-    } else {
-        target
+trait XmasNumber: Copy + Eq + Hash + FromStr {
+    fn checked_add(self, other: Self) -> Option<Self>;
+}
+
+impl XmasNumber for u64 {
+    fn checked_add(self, other: u64) -> Option<u64> {
+        u64::checked_add(self, other)
     }
 }
 
-fn part2(input: &[u64], target: u64) -> u64 {
-    let mut imin = 0;
-    let mut imax = 2;
-    loop {
-        let res: u64 = input[imin..imax].iter().sum();
-        if res > target {
-            imin += 1;
-        } else if res < target {
-            imax += 1;
-        } else {
-            let min = input[imin..imax].iter().min().unwrap();
-            let max = input[imin..imax].iter().max().unwrap();
-            break min + max;
+impl XmasNumber for u128 {
+    fn checked_add(self, other: u128) -> Option<u128> {
+        u128::checked_add(self, other)
+    }
+}
+
+// Checks numbers against the sums of pairs of the previous `preamble`
+// numbers, keeping a count of each pair sum in the window so every new
+// number costs O(preamble) rather than O(preamble²). Pair sums that overflow
+// can't match any number, so they aren't counted.
+struct XmasValidator<T> {
+    preamble: usize,
+    window: VecDeque<T>,
+    sums: HashMap<T, usize>,
+}
+
+impl<T: XmasNumber> XmasValidator<T> {
+    fn new(preamble: usize) -> XmasValidator<T> {
+        XmasValidator {
+            preamble,
+            // grows as numbers arrive, so a huge preamble costs nothing up front
+            window: VecDeque::new(),
+            sums: HashMap::new(),
+        }
+    }
+
+    fn is_valid(&self, n: T) -> bool {
+        self.window.len() < self.preamble || self.sums.contains_key(&n)
+    }
+
+    // whether `n` is the sum of two of the previous numbers (numbers in the
+    // preamble are always valid), then slides the window on to include it
+    fn push(&mut self, n: T) -> bool {
+        let valid = self.is_valid(n);
+        for sum in self.window.iter().filter_map(|&x| x.checked_add(n)) {
+            *self.sums.entry(sum).or_insert(0) += 1;
         }
+        self.window.push_back(n);
+        if self.window.len() > self.preamble {
+            let oldest = self.window.pop_front().unwrap();
+            for sum in self.window.iter().filter_map(|&x| x.checked_add(oldest)) {
+                let count = self.sums.get_mut(&sum).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    self.sums.remove(&sum);
+                }
+            }
+        }
+        valid
     }
 }
 
+// (index, number) of each number that isn't a sum of two of the `preamble`
+// numbers before it, produced as the numbers arrive. Works over unbounded
+// sources such as a channel's receiver.
+fn invalid_numbers<T, I>(numbers: I, preamble: usize) -> impl Iterator<Item = (usize, T)>
+where T: XmasNumber,
+      I: IntoIterator<Item = T>
+{
+    let mut validator = XmasValidator::new(preamble);
+    numbers.into_iter().enumerate().filter(move |&(_, n)| !validator.push(n))
+}
+
+// as `invalid_numbers`, reading a number per line and reporting the line
+// numbers of invalid numbers
+fn stream_invalid_numbers<T, R>(reader: R, preamble: usize) -> impl Iterator<Item = Result<(usize, T), ReadError>>
+where T: XmasNumber,
+      R: BufRead
+{
+    let mut validator = XmasValidator::new(preamble);
+    reader.lines().enumerate().filter_map(move |(line_no, line)| {
+        let line_no = line_no + 1;
+        let parsed = line
+            .map_err(|e| ReadError::IoError(Some(line_no), e))
+            .and_then(|line| line.trim().parse::<T>().map_err(|_| ReadError::ParseError(line_no, line)));
+        match parsed {
+            Ok(n) if validator.push(n) => None,
+            Ok(n) => Some(Ok((line_no, n))),
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+fn find_invalid(input: &[u64], preamble: usize) -> Vec<(usize, u64)> {
+    invalid_numbers(input.iter().copied(), preamble).collect()
+}
+
+// every range of at least two numbers that sums to `target`, ordered by
+// where the range ends
+fn find_contiguous_ranges(input: &[u64], target: u64) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    // prefix sum -> indexes it occurs at
+    let mut prefixes: HashMap<u128, Vec<usize>> = HashMap::new();
+    let mut prefix = 0u128;
+    prefixes.entry(prefix).or_default().push(0);
+    for (idx, &n) in input.iter().enumerate() {
+        prefix += n as u128;
+        let end = idx + 1;
+        if let Some(starts) = prefix.checked_sub(target as u128).and_then(|p| prefixes.get(&p)) {
+            ranges.extend(starts.iter().filter(|&&start| end - start >= 2).map(|&start| start..end));
+        }
+        prefixes.entry(prefix).or_default().push(end);
+    }
+    ranges
+}
+
+fn part1(input: &[u64], preamble: usize) -> Option<u64> {
+    find_invalid(input, preamble).first().map(|&(_, n)| n)
+}
+
+fn part2(input: &[u64], target: u64) -> Option<u64> {
+    find_contiguous_ranges(input, target).first().map(|range| {
+        let min = input[range.clone()].iter().min().unwrap();
+        let max = input[range.clone()].iter().max().unwrap();
+        min + max
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -69,8 +179,60 @@ mod test {
             309,
             576,
         ];
-        assert_eq!(part1(&input, 5), 127);
-        assert_eq!(part2(&input, 127), 62);
+        assert_eq!(part1(&input, 5), Some(127));
+        assert_eq!(part2(&input, 127), Some(62));
+        assert_eq!(find_invalid(&input, 5), vec![(14, 127)]);
+        assert_eq!(find_contiguous_ranges(&input, 127), vec![2..6]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_invalid_and_ranges() {
+        let input = vec![1, 2, 3, 3, 100, 6, 200, 0, 3, 3];
+        assert_eq!(find_invalid(&input, 2), vec![(3, 3), (4, 100), (5, 6), (6, 200), (7, 0), (8, 3)]);
+        // equal numbers at different positions can pair up, as 3 + 3 does
+        assert_eq!(find_invalid(&input, 3), vec![(4, 100), (6, 200), (7, 0), (8, 3)]);
+        assert_eq!(find_contiguous_ranges(&input, 6), vec![0..3, 2..4, 7..10, 8..10]);
+        assert_eq!(find_contiguous_ranges(&input, 3), vec![0..2, 7..9]);
+        assert_eq!(part2(&input, 7), None);
+        assert_eq!(part1(&[1, 2, 3], 2), None);
+
+        // a preamble longer than the input leaves every number valid
+        for &preamble in &[100_000_000_000, usize::MAX] {
+            let mut validator = XmasValidator::new(preamble);
+            assert!([1u64, 2, 3].iter().all(|&n| validator.push(n)));
+            assert!(find_invalid(&input, preamble).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_streaming() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "35\n20\n15\n25\n47\n40\n62\n55\n65\n95\n102\n117\n150\n182\n127\n219\n299\n277\n309\n576\n";
+        let invalid = stream_invalid_numbers::<u64, _>(input.as_bytes(), 5)
+            .collect::<Result<Vec<(usize, u64)>, ReadError>>()?;
+        assert_eq!(invalid, vec![(15, 127)]);
+
+        let big = u64::MAX as u128;
+        let input = format!("{}\n{}\n{}\n{}\n", big, big + 1, 2 * big + 1, 2 * big + 2);
+        let invalid = stream_invalid_numbers::<u128, _>(input.as_bytes(), 2)
+            .collect::<Result<Vec<(usize, u128)>, ReadError>>()?;
+        assert_eq!(invalid, vec![(4, 2 * big + 2)]);
+
+        let mut errors = stream_invalid_numbers::<u64, _>("1\n2\nthree\n".as_bytes(), 2);
+        assert!(matches!(errors.next(), Some(Err(ReadError::ParseError(3, _)))));
+
+        let input = vec![u128::MAX, u128::MAX, u128::MAX, 1];
+        assert_eq!(invalid_numbers(input, 2).collect::<Vec<_>>(), vec![(2, u128::MAX), (3, 1)]);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let producer = std::thread::spawn(move || {
+            for n in (1..=20u64).chain(Some(1000)).chain(Some(39)) {
+                tx.send(n).unwrap();
+            }
+        });
+        let invalid: Vec<(usize, u64)> = invalid_numbers(rx, 20).collect();
+        producer.join().unwrap();
+        assert_eq!(invalid, vec![(20, 1000)]);
         Ok(())
     }
 }
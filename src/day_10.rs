@@ -1,49 +1,441 @@
+use std::{env, fmt, cmp::Ordering, collections::{BTreeMap, VecDeque}, time::SystemTime};
 use adventofcode2020::read_input_lines;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = read_input_lines("day_10_input.txt")?;
-    let result = part1(input.clone());
-    println!("part1: {}", result);
-    let result = part2(input);
-    println!("part2: {}", result);
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // e.g. `cargo run --bin day_10 -- sample 3 --steps 1,3`
+    let steps = match args.iter().position(|a| a == "--steps") {
+        Some(idx) => {
+            let steps = args.get(idx + 1).ok_or("expected a step set after --steps")?
+                .split(',')
+                .map(|step| step.trim().parse::<usize>())
+                .collect::<Result<Vec<usize>, _>>()?;
+            args.drain(idx..=idx + 1);
+            steps
+        },
+        None => vec![1, 2, 3],
+    };
+    let adapters = Adapters::new(&input, &steps);
+    let count = args.get(1).map(|n| n.parse::<usize>()).transpose()?.unwrap_or(10);
+    let format = |chain: &[usize]| chain.iter().map(|j| j.to_string()).collect::<Vec<_>>().join(" ");
+    match args.first().map(|a| a.as_str()) {
+        Some("list") => {
+            for chain in adapters.arrangements().take(count) {
+                println!("{}", format(&chain));
+            }
+        },
+        Some("sample") => {
+            let seed = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_nanos() as u64;
+            let mut rng = Rng::new(seed);
+            let sampler = adapters.sampler();
+            for _ in 0..count {
+                let chain = sampler.sample(&mut rng).ok_or("no valid arrangement")?;
+                println!("{}", format(&chain));
+            }
+        },
+        Some(mode @ "longest") | Some(mode @ "shortest") => {
+            let chain = if mode == "longest" { adapters.longest_chain() } else { adapters.shortest_chain() };
+            let chain = chain.ok_or("no valid arrangement")?;
+            println!("{} adapters: {}", chain.len() - 2, format(&chain));
+            for (step, count) in difference_histogram(&chain) {
+                println!("  +{}: {}", step, count);
+            }
+        },
+        Some("count") => match args.get(1) {
+            Some(modulus) => {
+                let modulus = modulus.parse::<u64>()?;
+                if modulus == 0 {
+                    return Err("modulus must be positive".into());
+                }
+                println!("{}", adapters.count_arrangements_modulo(modulus));
+            },
+            None => println!("{}", adapters.count_arrangements()),
+        },
+        Some(mode) => return Err(format!("unknown mode: {}", mode).into()),
+        None => {
+            let result = part1(&adapters);
+            println!("part1: {}", result);
+            let result = part2(&adapters);
+            println!("part2: {}", result);
+        },
+    }
     Ok(())
 }
 
-fn part1(mut input: Vec<usize>) -> usize {
-    input.push(0);
-    input.sort();
+// xorshift64*, enough for sampling arrangements without pulling in a crate
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+// Just enough arbitrary-precision arithmetic for arrangement counts, which
+// grow exponentially with the number of adapters. Little-endian base 2^32
+// limbs with no trailing zero limbs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
 
-    let (one, three) = input.windows(2).fold((0, 0), |(one, three), pair| {
-        let diff = pair[1] - pair[0];
-        if diff == 1 {
-            (one + 1, three)
-        } else if diff == 3 {
-            (one, three + 1)
-        } else {
-            (one, three)
+    fn trim(mut self) -> BigUint {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
         }
-    });
+        self
+    }
 
-    one * (three + 1)
+    fn add(&self, other: &BigUint) -> BigUint {
+        let (long, short) = if self.0.len() >= other.0.len() { (self, other) } else { (other, self) };
+        let mut limbs = Vec::with_capacity(long.0.len() + 1);
+        let mut carry = 0u64;
+        for (i, &limb) in long.0.iter().enumerate() {
+            let sum = limb as u64 + short.0.get(i).copied().unwrap_or(0) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        BigUint(limbs)
+    }
+
+    // `self - other`, which must not be negative
+    fn sub(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0i64;
+        for (i, &limb) in self.0.iter().enumerate() {
+            let mut diff = limb as i64 - other.0.get(i).copied().unwrap_or(0) as i64 - borrow;
+            borrow = if diff < 0 { 1 } else { 0 };
+            diff += borrow << 32;
+            limbs.push(diff as u32);
+        }
+        assert_eq!(borrow, 0, "BigUint subtraction underflowed");
+        BigUint(limbs).trim()
+    }
+
+    // (quotient, remainder) of division by a small divisor
+    fn div_rem_small(&self, divisor: u32) -> (BigUint, u32) {
+        let mut limbs = vec![0; self.0.len()];
+        let mut rem = 0u64;
+        for (i, &limb) in self.0.iter().enumerate().rev() {
+            let current = (rem << 32) | limb as u64;
+            limbs[i] = (current / divisor as u64) as u32;
+            rem = current % divisor as u64;
+        }
+        (BigUint(limbs).trim(), rem as u32)
+    }
+
+    fn bits(&self) -> usize {
+        match self.0.last() {
+            Some(top) => self.0.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    // uniform in 0..self, by rejecting random numbers of the same bit length
+    // that are too large
+    fn random_below(&self, rng: &mut Rng) -> BigUint {
+        assert!(!self.is_zero(), "no BigUint below zero");
+        let bits = self.bits();
+        let top_mask = match bits % 32 {
+            0 => u32::MAX,
+            top_bits => (1 << top_bits) - 1,
+        };
+        loop {
+            let mut limbs: Vec<u32> = (0..self.0.len()).map(|_| (rng.next_u64() >> 32) as u32).collect();
+            *limbs.last_mut().unwrap() &= top_mask;
+            let candidate = BigUint(limbs).trim();
+            if candidate < *self {
+                return candidate;
+            }
+        }
+    }
 }
 
-fn part2(mut input: Vec<usize>) -> usize {
-    input.push(0);
-    input.sort();
-    input.push(input[input.len() - 1] + 3);
+impl From<u64> for BigUint {
+    fn from(n: u64) -> BigUint {
+        BigUint(vec![n as u32, (n >> 32) as u32]).trim()
+    }
+}
 
-    let mut weights = vec![0; input.len()];
-    weights[0] = 1;
-    for i in 0..(input.len() - 1) {
-        for j in 1.. {
-            if i + j < input.len() && input[i + j] - input[i] <= 3 {
-                weights[i + j] += weights[i];
-            } else {
-                break;
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.0.len().cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // peel off nine decimal digits at a time
+        let mut chunks = vec![];
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (quotient, rem) = n.div_rem_small(1_000_000_000);
+            chunks.push(rem);
+            n = quotient;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+// The outlet (0), the adapters and the device (the highest adapter plus the
+// largest step), sorted, with the joltage differences a chain may use.
+struct Adapters {
+    joltages: Vec<usize>,
+    steps: Vec<usize>,
+}
+
+impl Adapters {
+    fn new(adapters: &[usize], steps: &[usize]) -> Adapters {
+        let mut steps = steps.to_vec();
+        steps.sort_unstable();
+        steps.dedup();
+        let mut joltages = adapters.to_vec();
+        joltages.push(0);
+        joltages.sort_unstable();
+        let device = joltages[joltages.len() - 1] + steps.last().copied().unwrap_or(0);
+        joltages.push(device);
+        Adapters { joltages, steps }
+    }
+
+    fn device(&self) -> usize {
+        self.joltages.len() - 1
+    }
+
+    fn successors(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let max_step = self.steps.last().copied().unwrap_or(0);
+        let from = self.joltages[idx];
+        (idx + 1..self.joltages.len())
+            .take_while(move |&j| self.joltages[j] - from <= max_step)
+            .filter(move |&j| self.steps.binary_search(&(self.joltages[j] - from)).is_ok())
+    }
+
+    // number of ways to get from each joltage to the device, which takes
+    // O(n^2) bits, so only sampling keeps the whole table
+    fn arrangement_counts(&self) -> Vec<BigUint> {
+        let mut counts = vec![BigUint::default(); self.joltages.len()];
+        counts[self.device()] = BigUint::from(1);
+        for idx in (0..self.device()).rev() {
+            counts[idx] = self.successors(idx).fold(BigUint::default(), |sum, j| sum.add(&counts[j]));
+        }
+        counts
+    }
+
+    // the number of ways from the outlet to the device, working back from
+    // the device while only keeping the counts within one step of the
+    // current joltage
+    fn rolling_count<T: Clone, F: Fn(T, &T) -> T>(&self, zero: T, one: T, add: F) -> T {
+        let max_step = self.steps.last().copied().unwrap_or(0);
+        // (joltage, count), nearest first
+        let mut window: VecDeque<(usize, T)> = VecDeque::new();
+        window.push_front((self.joltages[self.device()], one));
+        for idx in (0..self.device()).rev() {
+            let from = self.joltages[idx];
+            while window.back().map(|(j, _)| j - from > max_step).unwrap_or(false) {
+                window.pop_back();
             }
+            let count = window.iter()
+                .filter(|(j, _)| self.steps.binary_search(&(j - from)).is_ok())
+                .fold(zero.clone(), |sum, (_, count)| add(sum, count));
+            window.push_front((from, count));
         }
+        window.pop_front().unwrap().1
+    }
+
+    fn count_arrangements(&self) -> BigUint {
+        self.rolling_count(BigUint::default(), BigUint::from(1), |sum, count| sum.add(count))
+    }
+
+    // the same count, modulo `modulus` (typically a large prime), without
+    // ever holding the full number
+    fn count_arrangements_modulo(&self, modulus: u64) -> u64 {
+        self.rolling_count(0, 1 % modulus, |sum, &count| {
+            ((sum as u128 + count as u128) % modulus as u128) as u64
+        })
+    }
+
+    fn reaches_device(&self) -> Vec<bool> {
+        let mut reaches = vec![false; self.joltages.len()];
+        reaches[self.device()] = true;
+        for idx in (0..self.device()).rev() {
+            reaches[idx] = self.successors(idx).any(|j| reaches[j]);
+        }
+        reaches
+    }
+
+    fn arrangements(&self) -> Arrangements<'_> {
+        Arrangements {
+            adapters: self,
+            reaches: self.reaches_device(),
+            path: vec![],
+            started: false,
+        }
+    }
+
+    fn sampler(&self) -> Sampler<'_> {
+        Sampler {
+            adapters: self,
+            counts: self.arrangement_counts(),
+        }
+    }
+
+    // the chain with the most (`longest`) or fewest adapters
+    fn extreme_chain(&self, longest: bool) -> Option<Vec<usize>> {
+        // (adapters to the device, next index) from each joltage
+        let mut best: Vec<Option<(usize, usize)>> = vec![None; self.joltages.len()];
+        best[self.device()] = Some((0, self.device()));
+        for idx in (0..self.device()).rev() {
+            best[idx] = self.successors(idx)
+                .filter_map(|j| best[j].map(|(len, _)| (len + 1, j)))
+                .fold(None, |acc, candidate| match acc {
+                    Some(current) if candidate.0 == current.0 || (candidate.0 > current.0) != longest => Some(current),
+                    _ => Some(candidate),
+                });
+        }
+        best[0]?;
+        let mut chain = vec![self.joltages[0]];
+        let mut idx = 0;
+        while idx != self.device() {
+            idx = best[idx].unwrap().1;
+            chain.push(self.joltages[idx]);
+        }
+        Some(chain)
+    }
+
+    fn longest_chain(&self) -> Option<Vec<usize>> {
+        self.extreme_chain(true)
+    }
+
+    fn shortest_chain(&self) -> Option<Vec<usize>> {
+        self.extreme_chain(false)
+    }
+}
+
+// Draws arrangements at random, sharing one table of counts between draws.
+struct Sampler<'a> {
+    adapters: &'a Adapters,
+    counts: Vec<BigUint>,
+}
+
+impl Sampler<'_> {
+    // picks each successor in proportion to the arrangements through it, so
+    // every arrangement is equally likely
+    fn sample(&self, rng: &mut Rng) -> Option<Vec<usize>> {
+        let adapters = self.adapters;
+        let counts = &self.counts;
+        if counts[0].is_zero() {
+            return None;
+        }
+        let mut chain = vec![adapters.joltages[0]];
+        let mut idx = 0;
+        while idx != adapters.device() {
+            let mut pick = counts[idx].random_below(rng);
+            idx = adapters.successors(idx)
+                .find(|&j| {
+                    if pick < counts[j] {
+                        true
+                    } else {
+                        pick = pick.sub(&counts[j]);
+                        false
+                    }
+                })
+                .unwrap();
+            chain.push(adapters.joltages[idx]);
+        }
+        Some(chain)
+    }
+}
+
+// Lazily walks every arrangement in lexicographic order of the indexes used,
+// never descending into joltages that can't reach the device.
+struct Arrangements<'a> {
+    adapters: &'a Adapters,
+    reaches: Vec<bool>,
+    path: Vec<usize>,
+    started: bool,
+}
+
+impl Arrangements<'_> {
+    fn next_viable(&self, idx: usize, after: Option<usize>) -> Option<usize> {
+        self.adapters.successors(idx)
+            .find(|&j| self.reaches[j] && after.map(|a| j > a).unwrap_or(true))
+    }
+
+    fn complete(&mut self) -> Vec<usize> {
+        while let Some(&idx) = self.path.last().filter(|&&idx| idx != self.adapters.device()) {
+            let next = self.next_viable(idx, None).unwrap();
+            self.path.push(next);
+        }
+        self.path.iter().map(|&idx| self.adapters.joltages[idx]).collect()
     }
-    weights[weights.len() - 1]
+}
+
+impl Iterator for Arrangements<'_> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        if !self.started {
+            self.started = true;
+            if !self.reaches[0] {
+                return None;
+            }
+            self.path.push(0);
+            return Some(self.complete());
+        }
+        loop {
+            let last = self.path.pop()?;
+            let &prev = self.path.last()?;
+            if let Some(next) = self.next_viable(prev, Some(last)) {
+                self.path.push(next);
+                return Some(self.complete());
+            }
+        }
+    }
+}
+
+// how many times each joltage difference occurs along a chain
+fn difference_histogram(chain: &[usize]) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for pair in chain.windows(2) {
+        *histogram.entry(pair[1] - pair[0]).or_insert(0) += 1;
+    }
+    histogram
+}
+
+fn part1(adapters: &Adapters) -> usize {
+    let histogram = difference_histogram(&adapters.joltages);
+    histogram.get(&1).unwrap_or(&0) * histogram.get(&3).unwrap_or(&0)
+}
+
+fn part2(adapters: &Adapters) -> BigUint {
+    adapters.count_arrangements()
 }
 
 
@@ -88,7 +480,7 @@ mod test {
             3,
         ];
 
-        assert_eq!(part1(input), 220);
+        assert_eq!(part1(&Adapters::new(&input, &[1, 2, 3])), 220);
 
         Ok(())
     }
@@ -109,9 +501,9 @@ mod test {
             4,
         ];
 
-        let result = part2(input);
+        let result = part2(&Adapters::new(&input, &[1, 2, 3]));
 
-        assert_eq!(8, result);
+        assert_eq!(BigUint::from(8), result);
 
         let input = vec![
             28,
@@ -147,11 +539,72 @@ mod test {
             3,
         ];
 
-        let result = part2(input);
+        let result = part2(&Adapters::new(&input, &[1, 2, 3]));
 
-        assert_eq!(19208, result);
+        assert_eq!(BigUint::from(19208), result);
 
 
         Ok(())
     }
+
+    #[test]
+    fn test_chains() {
+        let adapters = Adapters::new(&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4], &[1, 2, 3]);
+        let all: Vec<Vec<usize>> = adapters.arrangements().collect();
+        assert_eq!(all.len(), 8);
+        assert_eq!(all[0], vec![0, 1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19, 22]);
+        assert_eq!(all[7], vec![0, 1, 4, 7, 10, 12, 15, 16, 19, 22]);
+        assert!(all.windows(2).all(|pair| pair[0] != pair[1]));
+
+        assert_eq!(adapters.longest_chain(), Some(all[0].clone()));
+        assert_eq!(adapters.shortest_chain(), Some(all[7].clone()));
+        let expected: BTreeMap<usize, usize> = vec![(1, 7), (3, 5)].into_iter().collect();
+        assert_eq!(difference_histogram(&all[0]), expected);
+
+        let mut rng = Rng::new(2020);
+        let sampler = adapters.sampler();
+        for _ in 0..50 {
+            assert!(all.contains(&sampler.sample(&mut rng).unwrap()));
+        }
+
+        // without steps of 2, only 4 -> 5 -> 6 -> 7 can be shortcut
+        let adapters = Adapters::new(&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4], &[3, 1]);
+        assert_eq!(adapters.arrangements().collect::<Vec<_>>(), vec![
+            all[0].clone(),
+            vec![0, 1, 4, 7, 10, 11, 12, 15, 16, 19, 22],
+        ]);
+        assert_eq!(adapters.count_arrangements(), BigUint::from(2));
+
+        let adapters = Adapters::new(&[2, 4, 5], &[2]);
+        assert!(adapters.count_arrangements().is_zero());
+        assert_eq!(adapters.arrangements().next(), None);
+        assert_eq!(adapters.longest_chain(), None);
+        assert_eq!(adapters.sampler().sample(&mut rng), None);
+    }
+
+    #[test]
+    fn test_big_counts() {
+        // with every joltage present the counts are the tribonacci numbers
+        let adapters = Adapters::new(&(1..=100).collect::<Vec<usize>>(), &[1, 2, 3]);
+        assert_eq!(adapters.count_arrangements().to_string(), "180396380815100901214157639");
+        assert_eq!(adapters.count_arrangements_modulo(1_000_000_007), 347_873_931);
+
+        let adapters = Adapters::new(&(1..=30_000).collect::<Vec<usize>>(), &[1, 2, 3]);
+        let count = adapters.count_arrangements();
+        assert_eq!(count.to_string().len(), 7940);
+        assert!(count.to_string().starts_with("18818629821731047520"));
+        for &modulus in &[1_000_000_007, 998_244_353] {
+            assert_eq!(adapters.count_arrangements_modulo(modulus as u64), count.div_rem_small(modulus).1 as u64);
+        }
+
+        // gaps of three pin down a single chain
+        let adapters = Adapters::new(&(1..=20_000).map(|j| j * 3).collect::<Vec<usize>>(), &[1, 2, 3]);
+        assert_eq!(adapters.count_arrangements(), BigUint::from(1));
+
+        let big = BigUint::from(u64::MAX).add(&BigUint::from(1));
+        assert_eq!(big.to_string(), "18446744073709551616");
+        assert_eq!(big.sub(&BigUint::from(1)), BigUint::from(u64::MAX));
+        let mut rng = Rng::new(10);
+        assert!((0..100).all(|_| big.random_below(&mut rng) < big));
+    }
 }
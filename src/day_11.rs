@@ -1,5 +1,6 @@
 use adventofcode2020::{ReadError, parse_input_file};
-use std::iter::repeat;
+use std::{env, fmt, fs, io::{self, Write}, convert::TryFrom, str::FromStr};
+use std::collections::HashMap;
 
 #[derive(Clone, Copy)]
 enum Tile {
@@ -33,6 +34,127 @@ impl Into<usize> for Tile {
     }
 }
 
+const ORTHOGONAL: [(isize, isize); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+const ALL_DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1), (0, -1), (1, -1),
+    (-1, 0), (1, 0),
+    (-1, 1), (0, 1), (1, 1),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Neighbourhood {
+    // the eight adjacent tiles
+    Moore,
+    // the four orthogonally adjacent tiles
+    VonNeumann,
+    // the first seat seen in each of the eight directions, looking past
+    // floor no further than `max_distance` tiles if given
+    LineOfSight(Option<usize>),
+}
+
+impl Neighbourhood {
+    fn directions(&self) -> &'static [(isize, isize)] {
+        match self {
+            Neighbourhood::VonNeumann => &ORTHOGONAL,
+            _ => &ALL_DIRECTIONS,
+        }
+    }
+}
+
+impl FromStr for Neighbourhood {
+    type Err = String;
+
+    // `moore`, `vonneumann`, `sight` or `sight:<max distance>`
+    fn from_str(s: &str) -> Result<Neighbourhood, String> {
+        match s {
+            "moore" => Ok(Neighbourhood::Moore),
+            "vonneumann" => Ok(Neighbourhood::VonNeumann),
+            "sight" => Ok(Neighbourhood::LineOfSight(None)),
+            _ => s.strip_prefix("sight:")
+                .and_then(|d| d.parse::<usize>().ok())
+                .filter(|&d| d > 0)
+                .map(|d| Neighbourhood::LineOfSight(Some(d)))
+                .ok_or_else(|| format!("unknown neighbourhood: {}", s)),
+        }
+    }
+}
+
+// Which neighbour counts (0 to 8) satisfy a rule, as a bitmask.
+#[derive(Clone, Copy, PartialEq)]
+struct Counts(u16);
+
+impl Counts {
+    fn contains(&self, count: usize) -> bool {
+        count < 16 && self.0 & (1 << count) != 0
+    }
+}
+
+impl FromStr for Counts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Counts, String> {
+        s.chars().try_fold(Counts(0), |counts, c| match c.to_digit(10) {
+            Some(d) if d <= 8 => Ok(Counts(counts.0 | (1 << d))),
+            _ => Err(format!("invalid neighbour count: {}", c)),
+        })
+    }
+}
+
+impl fmt::Debug for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (0..=8).filter(|&c| self.contains(c)).try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
+// A seating policy: an empty seat fills when its occupied neighbour count is
+// in `birth`, and an occupied seat stays occupied while its count is in
+// `survival`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rules {
+    birth: Counts,
+    survival: Counts,
+    neighbourhood: Neighbourhood,
+}
+
+impl Rules {
+    fn part1() -> Rules {
+        Rules {
+            birth: Counts(0b1),
+            survival: Counts(0b1111),
+            neighbourhood: Neighbourhood::Moore,
+        }
+    }
+
+    fn part2() -> Rules {
+        Rules {
+            birth: Counts(0b1),
+            survival: Counts(0b11111),
+            neighbourhood: Neighbourhood::LineOfSight(None),
+        }
+    }
+}
+
+impl FromStr for Rules {
+    type Err = String;
+
+    // e.g. `B0/S0123 moore` or `B0/S01234 sight:3`
+    fn from_str(s: &str) -> Result<Rules, String> {
+        let mut parts = s.split_whitespace();
+        let conditions = parts.next().ok_or("expected birth/survival conditions")?;
+        let (birth, survival) = conditions.strip_prefix('B')
+            .and_then(|c| {
+                let mut split = c.splitn(2, "/S");
+                Some((split.next()?, split.next()?))
+            })
+            .ok_or_else(|| format!("expected B<counts>/S<counts>: {}", conditions))?;
+        let neighbourhood = parts.next().unwrap_or("moore").parse()?;
+        if parts.next().is_some() {
+            return Err(format!("unexpected trailing input in rules: {}", s));
+        }
+        Ok(Rules { birth: birth.parse()?, survival: survival.parse()?, neighbourhood })
+    }
+}
+
 #[derive(Clone)]
 struct State(Vec<Vec<Tile>>);
 
@@ -42,6 +164,23 @@ impl From<Vec<Vec<Tile>>> for State {
     }
 }
 
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in &self.0 {
+            for tile in row {
+                let c = match tile {
+                    Tile::EmptySeat => 'L',
+                    Tile::Occupied => '#',
+                    Tile::Floor => '.',
+                };
+                write!(f, "{}", c)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
 impl State {
 
     fn tile_at(&self, x: usize, y: usize) -> Option<Tile> {
@@ -50,128 +189,449 @@ impl State {
             .flatten()
     }
 
-    fn mutate<F>(&self, count_surrounding: F, comfort_threashold: usize) -> (State, bool)
-    where F: Fn(&Self, usize, usize) -> usize {
-        let mut new = vec![];
-        let mut diff = false;
-        for y in 0..self.0.len() {
-            let row = &self.0[y];
-            let mut new_row = vec![];
-            for x in 0..row.len() {
-                if let Some(t) = self.tile_at(x, y) {
-                    match t {
-                        Tile::Floor => new_row.push(Tile::Floor),
-                        Tile::Occupied => {
-                            let count = count_surrounding(self, x, y);
-                            if count >= comfort_threashold {
-                                new_row.push(Tile::EmptySeat);
-                                diff = true;
-                            } else {
-                                new_row.push(Tile::Occupied);
-                            }
-                        },
-                        Tile::EmptySeat => {
-                            let count = count_surrounding(self, x, y);
-                            if count == 0 {
-                                new_row.push(Tile::Occupied);
-                                diff = true;
-                            } else {
-                                new_row.push(Tile::EmptySeat);
-                            }
-                        }
-                    }
+    // the tile `distance` steps from (x, y) in direction (dx, dy), if it's
+    // on the floor plan
+    fn tile_towards(&self, x: usize, y: usize, (dx, dy): (isize, isize), distance: usize) -> Option<Tile> {
+        let x = x as isize + dx * distance as isize;
+        let y = y as isize + dy * distance as isize;
+        if x < 0 || y < 0 {
+            return None;
+        }
+        self.tile_at(x as usize, y as usize)
+    }
+
+    // the seats that count as neighbours of (x, y)
+    fn neighbour_seats(&self, x: usize, y: usize, neighbourhood: Neighbourhood) -> Vec<(usize, usize)> {
+        let position = |(dx, dy): (isize, isize), distance: usize| (
+            (x as isize + dx * distance as isize) as usize,
+            (y as isize + dy * distance as isize) as usize,
+        );
+        neighbourhood.directions().iter().filter_map(|&direction| {
+            let distance = match neighbourhood {
+                Neighbourhood::LineOfSight(max_distance) => (1..)
+                    .take_while(|&d| max_distance.map(|max| d <= max).unwrap_or(true))
+                    .map(|d| (d, self.tile_towards(x, y, direction, d)))
+                    .take_while(|(_, t)| t.is_some())
+                    .find(|(_, t)| !t.unwrap().is_floor())
+                    .map(|(d, _)| d),
+                _ => Some(1).filter(|&d| self.tile_towards(x, y, direction, d).map(|t| !t.is_floor()).unwrap_or(false)),
+            };
+            distance.map(|d| position(direction, d))
+        }).collect()
+    }
+
+    // runs until the seating settles or starts repeating, or for at most
+    // `max_steps` generations
+    fn simulate(&self, rules: &Rules, max_steps: Option<usize>) -> (State, Outcome) {
+        let mut simulation = Simulation::new(self, rules);
+        let outcome = simulation.run(max_steps);
+        (simulation.to_state(), outcome)
+    }
+
+    fn stabalize(self, rules: &Rules) -> State {
+        self.simulate(rules, None).0
+    }
+
+    fn count_occupied(&self) -> usize {
+        self.0.iter().map(|row| row.iter().filter(|t| t.is_occupied()).count()).sum()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    // nothing changed after `generation`
+    Stable { generation: usize },
+    // generation `start` recurs every `period` generations
+    Cycle { start: usize, period: usize },
+    StepLimit,
+}
+
+// The seats of a floor plan numbered in row-major order, with each seat's
+// neighbours under the rules' neighbourhood worked out once up front and
+// stored flat: seat i's neighbours are `neighbours[offsets[i]..offsets[i + 1]]`.
+// Every neighbourhood is symmetric, so only the seats that changed last
+// generation and their neighbours can change in the next one.
+//
+// Cycles are found with Brent's algorithm, comparing against a single saved
+// generation whose distance behind the current one doubles each time it's
+// replaced, so only a couple of copies of the seating are ever kept.
+#[derive(Clone)]
+struct Simulation {
+    width: Vec<usize>,
+    positions: Vec<(usize, usize)>,
+    offsets: Vec<usize>,
+    neighbours: Vec<u32>,
+    rules: Rules,
+    occupied: Vec<bool>,
+    next: Vec<bool>,
+    dirty: Vec<u32>,
+    is_dirty: Vec<bool>,
+    generation: usize,
+    initial: Vec<bool>,
+    tortoise: Vec<bool>,
+    tortoise_generation: usize,
+    power: usize,
+}
+
+impl Simulation {
+    fn new(state: &State, rules: &Rules) -> Simulation {
+        let mut positions = vec![];
+        let mut seat_ids = vec![];
+        for (y, row) in state.0.iter().enumerate() {
+            seat_ids.push(vec![None; row.len()]);
+            for (x, tile) in row.iter().enumerate() {
+                if !tile.is_floor() {
+                    seat_ids[y][x] = Some(positions.len() as u32);
+                    positions.push((x, y));
                 }
             }
-            new.push(new_row);
         }
-        (new.into(), diff)
+        let mut offsets = vec![0];
+        let mut neighbours = vec![];
+        for &(x, y) in &positions {
+            neighbours.extend(state.neighbour_seats(x, y, rules.neighbourhood).iter()
+                .map(|&(nx, ny)| seat_ids[ny][nx].unwrap()));
+            offsets.push(neighbours.len());
+        }
+        let occupied: Vec<bool> = positions.iter()
+            .map(|&(x, y)| state.0[y][x].is_occupied())
+            .collect();
+        Simulation {
+            width: state.0.iter().map(|row| row.len()).collect(),
+            next: occupied.clone(),
+            initial: occupied.clone(),
+            tortoise: occupied.clone(),
+            dirty: (0..positions.len() as u32).collect(),
+            is_dirty: vec![true; positions.len()],
+            positions,
+            offsets,
+            neighbours,
+            rules: *rules,
+            occupied,
+            generation: 0,
+            tortoise_generation: 0,
+            power: 1,
+        }
+    }
+
+    // steps once, returning how the run ended if it has. A cycle is noticed
+    // within about twice the generations it takes to first repeat.
+    fn advance(&mut self) -> Option<Outcome> {
+        if !self.step() {
+            return Some(Outcome::Stable { generation: self.generation });
+        }
+        if self.occupied == self.tortoise {
+            let period = self.generation - self.tortoise_generation;
+            return Some(Outcome::Cycle { start: self.cycle_start(period), period });
+        }
+        if self.generation - self.tortoise_generation == self.power {
+            self.tortoise.clone_from(&self.occupied);
+            self.tortoise_generation = self.generation;
+            self.power *= 2;
+        }
+        None
+    }
+
+    // the first generation that recurs, found by running two copies from the
+    // initial seating `period` generations apart until they agree
+    fn cycle_start(&self, period: usize) -> usize {
+        let mut behind = self.restarted();
+        let mut ahead = self.restarted();
+        for _ in 0..period {
+            ahead.step();
+        }
+        while behind.occupied != ahead.occupied {
+            behind.step();
+            ahead.step();
+        }
+        behind.generation
+    }
+
+    fn restarted(&self) -> Simulation {
+        let mut simulation = self.clone();
+        simulation.occupied.clone_from(&self.initial);
+        simulation.next.clone_from(&self.initial);
+        simulation.tortoise.clone_from(&self.initial);
+        simulation.dirty = (0..self.positions.len() as u32).collect();
+        simulation.is_dirty = vec![true; self.positions.len()];
+        simulation.generation = 0;
+        simulation.tortoise_generation = 0;
+        simulation.power = 1;
+        simulation
+    }
+
+    fn run(&mut self, max_steps: Option<usize>) -> Outcome {
+        let mut steps = 0;
+        while max_steps.map(|max| steps < max).unwrap_or(true) {
+            if let Some(outcome) = self.advance() {
+                return outcome;
+            }
+            steps += 1;
+        }
+        Outcome::StepLimit
     }
 
-    fn stabalize<F>(self, count_surrounding: &F, comfort_threashold: usize) -> State
-    where F: Fn(&Self, usize, usize) -> usize {
-        let r: Result<State, State> = (0..).try_fold(self, |state, _| {
-            let (state, mutated) = state.mutate(count_surrounding, comfort_threashold);
-            if mutated {
-                Ok(state)
+    fn neighbours_of(&self, seat: usize) -> &[u32] {
+        &self.neighbours[self.offsets[seat]..self.offsets[seat + 1]]
+    }
+
+    // advances a generation, returning whether any seat changed
+    fn step(&mut self) -> bool {
+        let mut changed = vec![];
+        for &seat in &self.dirty {
+            let seat = seat as usize;
+            let count = self.neighbours_of(seat).iter()
+                .filter(|&&n| self.occupied[n as usize])
+                .count();
+            let occupied = if self.occupied[seat] {
+                self.rules.survival.contains(count)
             } else {
-                Err(state)
+                self.rules.birth.contains(count)
+            };
+            if occupied != self.occupied[seat] {
+                self.next[seat] = occupied;
+                changed.push(seat);
+            }
+        }
+        std::mem::swap(&mut self.occupied, &mut self.next);
+        for &seat in &self.dirty {
+            self.is_dirty[seat as usize] = false;
+        }
+        self.dirty.clear();
+        for &seat in &changed {
+            // bring the other buffer up to date for the next generation
+            self.next[seat] = self.occupied[seat];
+            let neighbours = &self.neighbours[self.offsets[seat]..self.offsets[seat + 1]];
+            for &n in std::iter::once(&(seat as u32)).chain(neighbours) {
+                if !self.is_dirty[n as usize] {
+                    self.is_dirty[n as usize] = true;
+                    self.dirty.push(n);
+                }
             }
-        });
-        r.err().expect("unreachable!")
+        }
+        if !changed.is_empty() {
+            self.generation += 1;
+        }
+        !changed.is_empty()
     }
 
-    fn count_occupied(&self) -> usize {
-        self.0.iter().map(|row| row.iter().filter(|t| t.is_occupied()).count()).sum()
+    fn to_state(&self) -> State {
+        let mut tiles: Vec<Vec<Tile>> = self.width.iter().map(|&w| vec![Tile::Floor; w]).collect();
+        for (&(x, y), &occupied) in self.positions.iter().zip(&self.occupied) {
+            tiles[y][x] = if occupied { Tile::Occupied } else { Tile::EmptySeat };
+        }
+        tiles.into()
     }
 }
 
-fn parse_row(line_no: usize, line: String) -> Result<Vec<Tile>, ReadError> {
-    line.chars().map(|c| match c {
-        'L' => Ok(Tile::EmptySeat),
-        '#' => Ok(Tile::Occupied),
-        '.' => Ok(Tile::Floor),
-        _ => Err(ReadError::ParseError(line_no, format!("{}", c)))
-    }).collect()
+// Every distinct generation of a simulation, starting with the initial
+// layout, and how the simulation ended.
+struct History {
+    frames: Vec<State>,
+    outcome: Outcome,
 }
 
-fn count_adjacent_part_1(state: &State, x: usize, y: usize) -> usize {
-    let topleft = if x == 0 || y == 0 { 0 } else {
-        state.tile_at(x - 1, y - 1).map(|t| t.into()).unwrap_or(0)
-    };
-    let topmiddle = if y == 0 { 0 } else {
-        state.tile_at(x, y - 1).map(|t| t.into()).unwrap_or(0)
-    };
-    let topright = if y == 0 { 0 } else {
-        state.tile_at(x + 1, y - 1).map(|t| t.into()).unwrap_or(0)
-    };
-    let left = if x == 0 { 0 } else {
-        state.tile_at(x - 1, y).map(|t| t.into()).unwrap_or(0)
-    };
-    let right: usize = state.tile_at(x + 1, y).map(|t| t.into()).unwrap_or(0);
-    let bottomleft = if x == 0 { 0 } else {
-        state.tile_at(x - 1, y + 1).map(|t| t.into()).unwrap_or(0)
-    };
-    let bottommiddle: usize = state.tile_at(x, y + 1).map(|t| t.into()).unwrap_or(0);
-    let bottomright: usize = state.tile_at(x + 1, y + 1).map(|t| t.into()).unwrap_or(0);
-    topleft + topmiddle + topright + left + right + bottomleft + bottommiddle + bottomright
+// floor, empty seat, occupied seat and an unused fourth entry, as the
+// colour table size has to be a power of two
+const PALETTE: [[u8; 3]; 4] = [[0x30, 0x30, 0x30], [0xd0, 0xd0, 0xd0], [0xd0, 0x30, 0x30], [0, 0, 0]];
+
+impl History {
+    fn record(state: &State, rules: &Rules, max_steps: Option<usize>) -> History {
+        let mut frames = vec![state.clone()];
+        let mut simulation = Simulation::new(state, rules);
+        loop {
+            if max_steps.map(|max| frames.len() > max).unwrap_or(false) {
+                return History { frames, outcome: Outcome::StepLimit };
+            }
+            if let Some(outcome) = simulation.advance() {
+                // the cycle may have gone round again before it was noticed
+                if let Outcome::Cycle { start, period } = outcome {
+                    frames.truncate(start + period);
+                }
+                return History { frames, outcome };
+            }
+            frames.push(simulation.to_state());
+        }
+    }
+
+    fn write_ascii<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for (generation, frame) in self.frames.iter().enumerate() {
+            writeln!(out, "generation {}", generation)?;
+            writeln!(out, "{}", frame)?;
+        }
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "generation,occupied,empty")?;
+        for (generation, frame) in self.frames.iter().enumerate() {
+            let seats: usize = frame.0.iter().map(|row| row.iter().filter(|t| !t.is_floor()).count()).sum();
+            let occupied = frame.count_occupied();
+            writeln!(out, "{},{},{}", generation, occupied, seats - occupied)?;
+        }
+        Ok(())
+    }
+
+    // an animated GIF with each tile drawn as a `scale` pixel square,
+    // showing each generation for `delay` hundredths of a second
+    fn write_gif<W: Write>(&self, mut out: W, scale: usize, delay: u16) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "floor plan too large for a GIF");
+        let tiles_wide = self.frames[0].0.iter().map(|row| row.len()).max().unwrap_or(0);
+        let width = u16::try_from(tiles_wide * scale).map_err(|_| too_large())?;
+        let height = u16::try_from(self.frames[0].0.len() * scale).map_err(|_| too_large())?;
+
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // global colour table of 2^(1 + 1) entries
+        out.write_all(&[0b1000_0001, 0, 0])?;
+        for colour in &PALETTE {
+            out.write_all(colour)?;
+        }
+        // loop forever
+        out.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        for frame in &self.frames {
+            out.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+            out.write_all(&delay.to_le_bytes())?;
+            out.write_all(&[0x00, 0x00])?;
+            out.write_all(&[0x2c, 0, 0, 0, 0])?;
+            out.write_all(&width.to_le_bytes())?;
+            out.write_all(&height.to_le_bytes())?;
+            out.write_all(&[0x00])?;
+            let data = lzw_encode(&rasterize(frame, width as usize, scale), 2);
+            out.write_all(&[2])?;
+            for block in data.chunks(255) {
+                out.write_all(&[block.len() as u8])?;
+                out.write_all(block)?;
+            }
+            out.write_all(&[0x00])?;
+        }
+        out.write_all(&[0x3b])
+    }
 }
 
-macro_rules! check {
-    ($state:expr, $xiter:expr, $yiter:expr) => {
-        $xiter.zip($yiter)
-            .map(|(x, y)| $state.tile_at(x, y))
-            .take_while(Option::is_some)
-            .flat_map(|t| t)
-            .find(|t| !t.is_floor())
-            .map(|t| t.into())
-            .unwrap_or(0);
+// palette indexes, row by row
+fn rasterize(frame: &State, width: usize, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * frame.0.len() * scale);
+    for row in &frame.0 {
+        let mut line: Vec<u8> = row.iter()
+            .flat_map(|tile| {
+                let colour = match tile {
+                    Tile::Floor => 0,
+                    Tile::EmptySeat => 1,
+                    Tile::Occupied => 2,
+                };
+                std::iter::repeat_n(colour, scale)
+            })
+            .collect();
+        line.resize(width, 0);
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
     }
+    pixels
 }
 
-fn count_adjacent_part_2(state: &State, x: usize, y: usize) -> usize {
-    let topleft = check!(state, (0..x).rev(), (0..y).rev());
-    let topmiddle = check!(state, repeat(x), (0..y).rev());
-    let topright = check!(state, x+1.., (0..y).rev());
-    let left = check!(state, (0..x).rev(), repeat(y));
-    let right = check!(state, x+1.., repeat(y));
-    let bottomleft = check!(state, (0..x).rev(), y+1..);
-    let bottommiddle = check!(state, repeat(x), y+1..);
-    let bottomright = check!(state, x+1.., y+1..);
-    topleft + topmiddle + topright + left + right + bottomleft + bottommiddle + bottomright
+// GIF flavoured LZW: variable width codes packed least significant bit
+// first, restarting the dictionary once all 12-bit codes are used
+fn lzw_encode(pixels: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut emit = |code: u16, size: u8| {
+        buffer |= (code as u32) << bits;
+        bits += size;
+        while bits >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    };
+
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end + 1;
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    emit(clear, code_size);
+    if let Some((&first, rest)) = pixels.split_first() {
+        let mut prefix = first as u16;
+        for &pixel in rest {
+            if let Some(&code) = dictionary.get(&(prefix, pixel)) {
+                prefix = code;
+                continue;
+            }
+            emit(prefix, code_size);
+            if next_code == 4096 {
+                emit(clear, code_size);
+                dictionary.clear();
+                code_size = min_code_size + 1;
+                next_code = end + 1;
+            } else {
+                if next_code >= 1 << code_size {
+                    code_size += 1;
+                }
+                dictionary.insert((prefix, pixel), next_code);
+                next_code += 1;
+            }
+            prefix = pixel as u16;
+        }
+        emit(prefix, code_size);
+    }
+    emit(end, code_size);
+    if bits > 0 {
+        bytes.push(buffer as u8);
+    }
+    bytes
+}
+
+fn parse_row(line_no: usize, line: String) -> Result<Vec<Tile>, ReadError> {
+    line.chars().map(|c| match c {
+        'L' => Ok(Tile::EmptySeat),
+        '#' => Ok(Tile::Occupied),
+        '.' => Ok(Tile::Floor),
+        _ => Err(ReadError::ParseError(line_no, format!("{}", c)))
+    }).collect()
 }
 
 fn part1(state: State) -> usize {
-    let state = state.stabalize(&count_adjacent_part_1, 4);
+    let state = state.stabalize(&Rules::part1());
     state.count_occupied()
 }
 
 fn part2(state: State) -> usize {
-    let state = state.stabalize(&count_adjacent_part_2, 5);
+    let state = state.stabalize(&Rules::part2());
     state.count_occupied()
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let state: State = parse_input_file("day_11_input.txt", parse_row)?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(format) = args.first().filter(|&a| a == "frames" || a == "gif" || a == "csv") {
+        // e.g. `cargo run --bin day_11 -- gif seating.gif B0/S01234 sight`
+        let path = args.get(1).ok_or("expected an output file")?;
+        let rules = match args[2..].join(" ") {
+            rules if rules.is_empty() => Rules::part1(),
+            rules => rules.parse::<Rules>()?,
+        };
+        let history = History::record(&state, &rules, Some(10_000));
+        let out = io::BufWriter::new(fs::File::create(path)?);
+        match format.as_str() {
+            "frames" => history.write_ascii(out)?,
+            "gif" => history.write_gif(out, 4, 20)?,
+            _ => history.write_csv(out)?,
+        }
+        println!("{} generations written to {} ({:?})", history.frames.len(), path, history.outcome);
+        return Ok(());
+    }
+    let rules = args.join(" ");
+    if !rules.is_empty() {
+        // e.g. `cargo run --bin day_11 -- B0/S012 vonneumann`
+        let rules = rules.parse::<Rules>()?;
+        let (state, outcome) = state.simulate(&rules, Some(1_000_000));
+        println!("{:?}: {:?}, {} occupied", rules, outcome, state.count_occupied());
+        return Ok(());
+    }
     let result = part1(state.clone());
     println!("part1: {}", result);
     let result = part2(state);
@@ -185,9 +645,7 @@ mod test {
 
     use adventofcode2020::parse_input_lines;
 
-    #[test]
-    fn test_1() -> Result<(), Box<dyn std::error::Error>> {
-        let input = r#"L.LL.LL.LL
+    const EXAMPLE: &str = r#"L.LL.LL.LL
 LLLLLLL.LL
 L.L.L..L..
 LLLL.LL.LL
@@ -198,7 +656,9 @@ LLLLLLLLLL
 L.LLLLLL.L
 L.LLLLL.LL"#;
 
-        let state: State = parse_input_lines(input.as_bytes(), parse_row)?;
+    #[test]
+    fn test_1() -> Result<(), Box<dyn std::error::Error>> {
+        let state: State = parse_input_lines(EXAMPLE.as_bytes(), parse_row)?;
 
         assert_eq!(part1(state.clone()), 37);
 
@@ -206,4 +666,119 @@ L.LLLLL.LL"#;
 
         Ok(())
     }
+
+    #[test]
+    fn test_rules() -> Result<(), Box<dyn std::error::Error>> {
+        let state: State = parse_input_lines(EXAMPLE.as_bytes(), parse_row)?;
+
+        assert_eq!("B0/S0123".parse::<Rules>()?, Rules::part1());
+        assert_eq!("B0/S01234 sight".parse::<Rules>()?, Rules::part2());
+        // seeing a single tile away is the same as looking at adjacent tiles
+        let rules = "B0/S0123 sight:1".parse::<Rules>()?;
+        assert_eq!(state.clone().stabalize(&rules).count_occupied(), 37);
+
+        let rules = "B0/S0123 vonneumann".parse::<Rules>()?;
+        assert_eq!(state.clone().stabalize(&rules).count_occupied(), 63);
+        let rules = "B0/S0123 sight:2".parse::<Rules>()?;
+        assert_eq!(state.clone().stabalize(&rules).count_occupied(), 25);
+
+        assert!("B9/S0".parse::<Rules>().is_err());
+        assert!("S0/B0".parse::<Rules>().is_err());
+        assert!("B0/S0 sight:0".parse::<Rules>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_floor_plan() {
+        // a 60x60 hall of seats with every fifth column an aisle
+        let tiles: Vec<Vec<Tile>> = (0..60)
+            .map(|_| (0..60).map(|x| if x % 5 == 4 { Tile::Floor } else { Tile::EmptySeat }).collect())
+            .collect();
+        let state = State::from(tiles);
+        let simulation = Simulation::new(&state, &Rules::part2());
+        assert_eq!(simulation.neighbours_of(0).len(), 3);
+        assert_eq!(simulation.neighbours_of(81).len(), 8);
+
+        // every generation matches recomputing every seat from scratch
+        for rules in &[Rules::part1(), Rules::part2()] {
+            let mut simulation = Simulation::new(&state, rules);
+            let mut expected = state.clone();
+            while simulation.step() {
+                expected = reference_step(&expected, rules);
+                assert_eq!(simulation.to_state().to_string(), expected.to_string());
+            }
+            assert_eq!(reference_step(&expected, rules).to_string(), expected.to_string());
+            assert!(simulation.generation > 1);
+        }
+    }
+
+    fn reference_step(state: &State, rules: &Rules) -> State {
+        let tiles: Vec<Vec<Tile>> = state.0.iter().enumerate().map(|(y, row)| {
+            row.iter().enumerate().map(|(x, &tile)| {
+                if tile.is_floor() {
+                    return tile;
+                }
+                let count = state.neighbour_seats(x, y, rules.neighbourhood).iter()
+                    .filter(|&&(nx, ny)| state.0[ny][nx].is_occupied())
+                    .count();
+                let occupied = if tile.is_occupied() {
+                    rules.survival.contains(count)
+                } else {
+                    rules.birth.contains(count)
+                };
+                if occupied { Tile::Occupied } else { Tile::EmptySeat }
+            }).collect()
+        }).collect();
+        tiles.into()
+    }
+
+    #[test]
+    fn test_history() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "L.L\nLLL\n";
+        let state: State = parse_input_lines(input.as_bytes(), parse_row)?;
+        let history = History::record(&state, &Rules::part1(), None);
+        assert_eq!(history.frames.len(), 3);
+        assert_eq!(history.outcome, Outcome::Stable { generation: 2 });
+
+        let mut csv = vec![];
+        history.write_csv(&mut csv)?;
+        assert_eq!(String::from_utf8(csv)?, "generation,occupied,empty\n0,0,5\n1,5,0\n2,4,1\n");
+
+        let mut ascii = vec![];
+        history.write_ascii(&mut ascii)?;
+        assert!(String::from_utf8(ascii)?.ends_with("generation 2\n#.#\n#L#\n\n"));
+
+        let mut gif = vec![];
+        history.write_gif(&mut gif, 2, 50)?;
+        assert_eq!(&gif[..10], b"GIF89a\x06\x00\x04\x00");
+        assert_eq!(gif.last(), Some(&0x3b));
+
+        // 3 bit codes: clear, 1, then 6 for the "1 1" it's just seen, end
+        assert_eq!(lzw_encode(&[1, 1, 1], 2), vec![0b1000_1100, 0b0000_1011]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cycles() -> Result<(), Box<dyn std::error::Error>> {
+        let state: State = parse_input_lines(EXAMPLE.as_bytes(), parse_row)?;
+
+        let (_, outcome) = state.simulate(&"B0/S01 vonneumann".parse()?, None);
+        assert_eq!(outcome, Outcome::Cycle { start: 16, period: 2 });
+        // everyone sits down, then everyone gets up again
+        let (_, outcome) = state.simulate(&"B0/S".parse()?, None);
+        assert_eq!(outcome, Outcome::Cycle { start: 0, period: 2 });
+
+        assert_eq!(state.simulate(&Rules::part1(), Some(5)).1, Outcome::StepLimit);
+        let (stable, outcome) = state.simulate(&Rules::part1(), Some(6));
+        assert_eq!(outcome, Outcome::Stable { generation: 5 });
+        assert_eq!(stable.count_occupied(), 37);
+
+        let history = History::record(&state, &"B0/S01 vonneumann".parse()?, None);
+        assert_eq!(history.frames.len(), 18);
+        assert_eq!(history.outcome, Outcome::Cycle { start: 16, period: 2 });
+        let history = History::record(&state, &"B0/S01 vonneumann".parse()?, Some(4));
+        assert_eq!(history.frames.len(), 5);
+        assert_eq!(history.outcome, Outcome::StepLimit);
+        Ok(())
+    }
 }
@@ -1,113 +1,323 @@
-use std::{fs, io::{BufReader, BufRead}};
-
-#[derive(Clone, Copy, Debug)]
-enum Direction {
-    North,
-    South,
-    East,
-    West
-}
-
-const RIGHT_DIRS: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
-const LEFT_DIRS: [Direction; 4] = [Direction::West, Direction::South, Direction::East, Direction::North];
-
-impl Direction {
-    fn right(&self, arg: i32) -> Direction {
-        let arg = (arg % 360 / 90) as usize;
-        let s: usize = match self {
-            Direction::North => 0,
-            Direction::East => 1,
-            Direction::South => 2,
-            Direction::West => 3,
+use std::{env, fmt, fs, io::{self, Write}, str::FromStr};
+use adventofcode2020::read_input_lines;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NavCommand {
+    North(f64),
+    South(f64),
+    East(f64),
+    West(f64),
+    // degrees anticlockwise
+    Left(f64),
+    // degrees clockwise
+    Right(f64),
+    Forward(f64),
+}
+
+impl FromStr for NavCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NavCommand, String> {
+        let s = s.trim();
+        let mut chars = s.chars();
+        let command = chars.next().ok_or("empty command")?;
+        let argument = chars.as_str().parse::<f64>()
+            .ok()
+            .filter(|a| a.is_finite())
+            .ok_or_else(|| format!("invalid argument: {}", s))?;
+        match command {
+            'N' => Ok(NavCommand::North(argument)),
+            'S' => Ok(NavCommand::South(argument)),
+            'E' => Ok(NavCommand::East(argument)),
+            'W' => Ok(NavCommand::West(argument)),
+            'L' => Ok(NavCommand::Left(argument)),
+            'R' => Ok(NavCommand::Right(argument)),
+            'F' => Ok(NavCommand::Forward(argument)),
+            _ => Err(format!("unknown command: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for NavCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (command, argument) = match self {
+            NavCommand::North(n) => ('N', n),
+            NavCommand::South(n) => ('S', n),
+            NavCommand::East(n) => ('E', n),
+            NavCommand::West(n) => ('W', n),
+            NavCommand::Left(n) => ('L', n),
+            NavCommand::Right(n) => ('R', n),
+            NavCommand::Forward(n) => ('F', n),
+        };
+        write!(f, "{}{}", command, argument)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Position {
+    x: f64,
+    y: f64,
+}
+
+impl Position {
+    fn new(x: f64, y: f64) -> Position {
+        Position { x, y }
+    }
+
+    fn offset(&self, dx: f64, dy: f64) -> Position {
+        Position::new(self.x + dx, self.y + dy)
+    }
+
+    // anticlockwise about the origin. Quarter turns are done by swapping
+    // coordinates so that the usual 90° steps stay exact.
+    fn rotate(&self, degrees: f64) -> Position {
+        let degrees = degrees.rem_euclid(360.0);
+        if degrees % 90.0 == 0.0 {
+            (0..(degrees / 90.0) as usize).fold(*self, |p, _| Position::new(-p.y, p.x))
+        } else {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            Position::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+        }
+    }
+
+    fn manhattan_distance(&self) -> f64 {
+        self.x.abs() + self.y.abs()
+    }
+
+    fn distance_to(&self, other: &Position) -> f64 {
+        (other.x - self.x).hypot(other.y - self.y)
+    }
+}
+
+// How a ship responds to navigation commands.
+trait Ship {
+    fn apply(&mut self, command: &NavCommand);
+
+    fn position(&self) -> Position;
+
+    // where the ship's waypoint is, relative to the ship, if it steers by one
+    fn waypoint(&self) -> Option<Position> {
+        None
+    }
+
+    fn navigate(&mut self, commands: &[NavCommand]) {
+        for command in commands {
+            self.apply(command);
+        }
+    }
+}
+
+// Directions move the ship, and it turns and sails along its heading.
+struct HeadingShip {
+    position: Position,
+    // unit vector
+    heading: Position,
+}
+
+impl HeadingShip {
+    fn new() -> HeadingShip {
+        HeadingShip { position: Position::new(0.0, 0.0), heading: Position::new(1.0, 0.0) }
+    }
+}
+
+impl Ship for HeadingShip {
+    fn apply(&mut self, command: &NavCommand) {
+        self.position = match *command {
+            NavCommand::North(n) => self.position.offset(0.0, n),
+            NavCommand::South(n) => self.position.offset(0.0, -n),
+            NavCommand::East(n) => self.position.offset(n, 0.0),
+            NavCommand::West(n) => self.position.offset(-n, 0.0),
+            NavCommand::Left(degrees) => {
+                self.heading = self.heading.rotate(degrees);
+                self.position
+            },
+            NavCommand::Right(degrees) => {
+                self.heading = self.heading.rotate(-degrees);
+                self.position
+            },
+            NavCommand::Forward(n) => self.position.offset(self.heading.x * n, self.heading.y * n),
+        };
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+}
+
+// Directions move a waypoint relative to the ship, which turns about the
+// ship, and the ship sails towards it.
+struct WaypointShip {
+    position: Position,
+    waypoint: Position,
+}
+
+impl WaypointShip {
+    fn new() -> WaypointShip {
+        WaypointShip { position: Position::new(0.0, 0.0), waypoint: Position::new(10.0, 1.0) }
+    }
+}
+
+impl Ship for WaypointShip {
+    fn apply(&mut self, command: &NavCommand) {
+        match *command {
+            NavCommand::North(n) => self.waypoint = self.waypoint.offset(0.0, n),
+            NavCommand::South(n) => self.waypoint = self.waypoint.offset(0.0, -n),
+            NavCommand::East(n) => self.waypoint = self.waypoint.offset(n, 0.0),
+            NavCommand::West(n) => self.waypoint = self.waypoint.offset(-n, 0.0),
+            NavCommand::Left(degrees) => self.waypoint = self.waypoint.rotate(degrees),
+            NavCommand::Right(degrees) => self.waypoint = self.waypoint.rotate(-degrees),
+            NavCommand::Forward(n) => {
+                self.position = self.position.offset(self.waypoint.x * n, self.waypoint.y * n);
+            },
+        }
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    fn waypoint(&self) -> Option<Position> {
+        Some(self.waypoint)
+    }
+}
+
+// Where the ship (and its waypoint, in absolute terms) was before the first
+// command and after each command.
+struct TrackPoint {
+    command: Option<NavCommand>,
+    ship: Position,
+    waypoint: Option<Position>,
+}
+
+struct Track(Vec<TrackPoint>);
+
+impl Track {
+    fn record<S: Ship>(ship: &mut S, commands: &[NavCommand]) -> Track {
+        let point = |command: Option<NavCommand>, ship: &S| TrackPoint {
+            command,
+            ship: ship.position(),
+            waypoint: ship.waypoint().map(|w| ship.position().offset(w.x, w.y)),
         };
-        RIGHT_DIRS[(s + arg) % 4]
-    }
-    fn left(&self, arg: i32) -> Direction {
-        let arg = (arg % 360 / 90) as usize;
-        let s: usize = match self {
-            Direction::North => 3,
-            Direction::East => 2,
-            Direction::South => 1,
-            Direction::West => 0,
+        let mut points = vec![point(None, ship)];
+        for command in commands {
+            ship.apply(command);
+            points.push(point(Some(*command), ship));
+        }
+        Track(points)
+    }
+
+    fn ship_path(&self) -> Vec<Position> {
+        self.0.iter().map(|point| point.ship).collect()
+    }
+
+    fn waypoint_path(&self) -> Option<Vec<Position>> {
+        self.0.iter().map(|point| point.waypoint).collect()
+    }
+
+    fn total_distance(&self) -> f64 {
+        self.0.windows(2).map(|pair| pair[0].ship.distance_to(&pair[1].ship)).sum()
+    }
+
+    // (south west, north east) corners around the ship and waypoint
+    fn bounding_box(&self) -> (Position, Position) {
+        let positions = self.0.iter().flat_map(|point| Some(point.ship).into_iter().chain(point.waypoint));
+        let first = self.0[0].ship;
+        positions.fold((first, first), |(min, max), p| (
+            Position::new(min.x.min(p.x), min.y.min(p.y)),
+            Position::new(max.x.max(p.x), max.y.max(p.y)),
+        ))
+    }
+
+    fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "step,command,ship_x,ship_y,waypoint_x,waypoint_y")?;
+        for (step, point) in self.0.iter().enumerate() {
+            let command = point.command.map(|c| c.to_string()).unwrap_or_default();
+            let (wx, wy) = point.waypoint
+                .map(|w| (w.x.to_string(), w.y.to_string()))
+                .unwrap_or_default();
+            writeln!(out, "{},{},{},{},{},{}", step, command, point.ship.x, point.ship.y, wx, wy)?;
+        }
+        Ok(())
+    }
+
+    // a feature collection of line strings in the puzzle's own east/north
+    // units rather than longitude and latitude
+    fn write_geojson<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let line_string = |name: &str, path: &[Position]| {
+            let coordinates = path.iter()
+                .map(|p| format!("[{},{}]", p.x, p.y))
+                .collect::<Vec<_>>()
+                .join(",");
+            format!(
+                r#"{{"type":"Feature","properties":{{"name":"{}"}},"geometry":{{"type":"LineString","coordinates":[{}]}}}}"#,
+                name, coordinates)
         };
-        LEFT_DIRS[(s + arg) % 4]
-    }
-}
-
-fn part1<R>(reader: R) -> i32
-where R: BufRead {
-    let (_, x, y) = reader.lines().enumerate()
-        .fold((Direction::East, 0i32, 0i32), |state, (line_no, line)| {
-            let line = line
-                .unwrap_or_else(|_| panic!("Error reading line: {}", line_no + 1));
-            let (command, argument) = line.split_at(1);
-            let argument = argument.parse::<i32>()
-                .unwrap_or_else(|_| panic!("Error parsing argument on line: {}", line_no + 1));
-            match command {
-                "N" => (state.0, state.1, state.2 + argument),
-                "S" => (state.0, state.1, state.2 - argument),
-                "E" => (state.0, state.1 + argument, state.2),
-                "W" => (state.0, state.1 - argument, state.2),
-                "L" => (state.0.left(argument), state.1, state.2),
-                "R" => (state.0.right(argument), state.1, state.2),
-                "F" => match state.0 {
-                    Direction::North => (state.0, state.1, state.2 + argument),
-                    Direction::South => (state.0, state.1, state.2 - argument),
-                    Direction::East => (state.0, state.1 + argument, state.2),
-                    Direction::West => (state.0, state.1 - argument, state.2),
-                },
-                _ => panic!("Invalid command on line: {}", line_no + 1)
-            }
-        });
-    x.abs() + y.abs()
-}
-
-fn part2<R>(reader: R) -> i32
-where R: BufRead {
-    let (_, _, sx, sy) = reader.lines().enumerate()
-        .fold((10i32, 1i32, 0i32, 0i32), |(wx, wy, sx, sy), (line_no, line)| {
-            let line = line
-                .unwrap_or_else(|_| panic!("Error reading line: {}", line_no + 1));
-            let (command, argument) = line.split_at(1);
-            let argument = argument.parse::<i32>()
-                .unwrap_or_else(|_| panic!("Error parsing argument on line: {}", line_no + 1));
-            match command {
-                "N" => (wx, wy + argument, sx, sy),
-                "S" => (wx, wy - argument, sx, sy),
-                "E" => (wx + argument, wy, sx, sy),
-                "W" => (wx - argument, wy, sx, sy),
-                "L" => {
-                    let (wx, wy) = (0..(argument % 360 / 90)).fold((wx, wy), |(x, y), _| {
-                        (-y, x)
-                    });
-                    (wx, wy, sx, sy)
-                },
-                "R" => {
-                    let (wx, wy) = (0..(argument % 360 / 90)).fold((wx, wy), |(x, y), _| {
-                        (y, -x)
-                    });
-                    (wx, wy, sx, sy)
-                },
-                "F" => {
-                    (wx, wy, sx + wx * argument, sy + wy * argument)
-                },
-                _ => panic!("Invalid command on line: {}", line_no + 1)
-            }
-        });
-    sx.abs() + sy.abs()
+        let mut features = vec![line_string("ship", &self.ship_path())];
+        if let Some(path) = self.waypoint_path() {
+            features.push(line_string("waypoint", &path));
+        }
+        writeln!(out, r#"{{"type":"FeatureCollection","features":[{}]}}"#, features.join(","))
+    }
+
+    // north is up, so y is flipped into SVG's downward coordinates
+    fn write_svg<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (min, max) = self.bounding_box();
+        let margin = ((max.x - min.x).max(max.y - min.y) / 20.0).max(1.0);
+        let points = |path: &[Position]| path.iter()
+            // adding zero turns -0 into 0
+            .map(|p| format!("{},{}", p.x, -p.y + 0.0))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+            min.x - margin, -max.y - margin, max.x - min.x + 2.0 * margin, max.y - min.y + 2.0 * margin)?;
+        if let Some(path) = self.waypoint_path() {
+            writeln!(out, r#"  <polyline points="{}" fill="none" stroke="grey" stroke-dasharray="{}" vector-effect="non-scaling-stroke"/>"#,
+                points(&path), margin / 2.0)?;
+        }
+        writeln!(out, r#"  <polyline points="{}" fill="none" stroke="navy" vector-effect="non-scaling-stroke"/>"#,
+            points(&self.ship_path()))?;
+        writeln!(out, "</svg>")
+    }
+}
+
+fn part1(commands: &[NavCommand]) -> f64 {
+    let mut ship = HeadingShip::new();
+    ship.navigate(commands);
+    ship.position().manhattan_distance()
+}
+
+fn part2(commands: &[NavCommand]) -> f64 {
+    let mut ship = WaypointShip::new();
+    ship.navigate(commands);
+    ship.position().manhattan_distance()
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let file = fs::File::open("day_12_input.txt")?;
-    let reader = BufReader::new(file);
-    let result = part1(reader);
+    let commands = read_input_lines::<NavCommand>("day_12_input.txt")?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [format, path, rest @ ..] = args.as_slice() {
+        // e.g. `cargo run --bin day_12 -- svg route.svg waypoint`
+        let track = match rest.first().map(|mode| mode.as_str()) {
+            Some("waypoint") => Track::record(&mut WaypointShip::new(), &commands),
+            Some("heading") | None => Track::record(&mut HeadingShip::new(), &commands),
+            Some(mode) => return Err(format!("unknown mode: {}", mode).into()),
+        };
+        let out = io::BufWriter::new(fs::File::create(path)?);
+        match format.as_str() {
+            "csv" => track.write_csv(out)?,
+            "geojson" => track.write_geojson(out)?,
+            "svg" => track.write_svg(out)?,
+            _ => return Err(format!("unknown format: {}", format).into()),
+        }
+        let (min, max) = track.bounding_box();
+        println!("bounding box: ({}, {}) to ({}, {})", min.x, min.y, max.x, max.y);
+        println!("distance travelled: {}", track.total_distance());
+        return Ok(());
+    }
+    let result = part1(&commands);
     println!("part1: {}", result);
-
-    let file = fs::File::open("day_12_input.txt")?;
-    let reader = BufReader::new(file);
-    let result = part2(reader);
+    let result = part2(&commands);
     println!("part2: {}", result);
-
     Ok(())
 }
 
@@ -115,6 +325,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 mod test {
     use super::*;
 
+    fn parse(input: &str) -> Result<Vec<NavCommand>, String> {
+        input.lines().map(|line| line.parse()).collect()
+    }
+
     #[test]
     fn test_1() -> Result<(), Box<dyn std::error::Error>> {
         let input = r#"F10
@@ -123,8 +337,69 @@ F7
 R90
 F11
 "#;
-        assert_eq!(part1(input.as_bytes()), 25);
-        assert_eq!(part2(input.as_bytes()), 286);
+        let commands = parse(input)?;
+        assert_eq!(part1(&commands), 25.0);
+        assert_eq!(part2(&commands), 286.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(parse("R45\nF2.5\nL-30")?, vec![
+            NavCommand::Right(45.0),
+            NavCommand::Forward(2.5),
+            NavCommand::Left(-30.0),
+        ]);
+        assert!("X10".parse::<NavCommand>().is_err());
+        assert!("F".parse::<NavCommand>().is_err());
+        assert!("".parse::<NavCommand>().is_err());
+        assert!("Finf".parse::<NavCommand>().is_err());
+
+        let mut ship = HeadingShip::new();
+        ship.navigate(&parse("L45\nF2\nR135\nF2")?);
+        let position = ship.position();
+        assert!((position.x - 2f64.sqrt()).abs() < 1e-9);
+        assert!((position.y - (2f64.sqrt() - 2.0)).abs() < 1e-9);
+
+        // turning a full circle in odd steps comes back to the same waypoint
+        let mut ship = WaypointShip::new();
+        ship.navigate(&parse("R100\nR100\nR160\nL450\nR90\nF1")?);
+        assert!((ship.position().x - 10.0).abs() < 1e-9);
+        assert!((ship.position().y - 1.0).abs() < 1e-9);
+
+        let mut ship = WaypointShip::new();
+        ship.navigate(&parse("L180\nR-90\nF3")?);
+        assert_eq!(ship.position(), Position::new(3.0, -30.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_track() -> Result<(), Box<dyn std::error::Error>> {
+        let commands = parse("F10\nN3\nF7\nR90\nF11")?;
+
+        let track = Track::record(&mut HeadingShip::new(), &commands);
+        assert_eq!(track.0.len(), 6);
+        assert_eq!(track.total_distance(), 31.0);
+        assert_eq!(track.bounding_box(), (Position::new(0.0, -8.0), Position::new(17.0, 3.0)));
+        assert!(track.waypoint_path().is_none());
+        let mut csv = vec![];
+        track.write_csv(&mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert_eq!(csv.lines().nth(1), Some("0,,0,0,,"));
+        assert_eq!(csv.lines().last(), Some("5,F11,17,-8,,"));
+
+        let track = Track::record(&mut WaypointShip::new(), &commands);
+        assert_eq!(track.0[2].waypoint, Some(Position::new(110.0, 14.0)));
+        assert_eq!(track.bounding_box(), (Position::new(0.0, -82.0), Position::new(218.0, 42.0)));
+        let mut geojson = vec![];
+        track.write_geojson(&mut geojson)?;
+        let geojson = String::from_utf8(geojson)?;
+        assert!(geojson.contains(r#""coordinates":[[0,0],[100,10],[100,10],[170,38],[170,38],[214,-72]]"#));
+        assert!(geojson.contains(r#""name":"waypoint""#));
+        let mut svg = vec![];
+        track.write_svg(&mut svg)?;
+        let svg = String::from_utf8(svg)?;
+        assert!(svg.contains(r#"points="0,0 100,-10 100,-10 170,-38 170,-38 214,72""#));
         Ok(())
     }
 }
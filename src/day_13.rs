@@ -1,6 +1,109 @@
-use std::{fs, io::{BufRead, BufReader}};
+use std::{env, fmt, fs, io::{BufRead, BufReader}};
 
 use adventofcode2020::ReadError;
+use number_theory::{CrtError, crt};
+
+mod number_theory {
+    use std::fmt;
+
+    // (g, x, y) with a * x + b * y = g = gcd(a, b)
+    pub fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+        let (mut old_r, mut r) = (a, b);
+        let (mut old_x, mut x) = (1, 0);
+        let (mut old_y, mut y) = (0, 1);
+        while r != 0 {
+            let q = old_r / r;
+            let next = (r, old_r - q * r);
+            old_r = next.0;
+            r = next.1;
+            let next = (x, old_x - q * x);
+            old_x = next.0;
+            x = next.1;
+            let next = (y, old_y - q * y);
+            old_y = next.0;
+            y = next.1;
+        }
+        if old_r < 0 {
+            (-old_r, -old_x, -old_y)
+        } else {
+            (old_r, old_x, old_y)
+        }
+    }
+
+    pub fn mod_inverse(a: i128, modulus: i128) -> Option<i128> {
+        let (g, x, _) = extended_gcd(a.rem_euclid(modulus), modulus);
+        if g == 1 {
+            Some(x.rem_euclid(modulus))
+        } else {
+            None
+        }
+    }
+
+    // a * b mod m for a, b in 0..m, falling back to doubling when the
+    // product doesn't fit
+    pub fn mul_mod(a: i128, b: i128, modulus: i128) -> i128 {
+        if let Some(product) = a.checked_mul(b) {
+            return product % modulus;
+        }
+        let (mut a, mut b, mut result) = (a, b, 0);
+        while b > 0 {
+            if b & 1 == 1 {
+                result = (result + a) % modulus;
+            }
+            a = (a << 1) % modulus;
+            b >>= 1;
+        }
+        result
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum CrtError {
+        InvalidModulus(i128),
+        // the two congruences x = residue (mod modulus) that can't both hold
+        NoSolution((i128, i128), (i128, i128)),
+        Overflow,
+    }
+
+    impl fmt::Display for CrtError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                CrtError::InvalidModulus(m) => write!(f, "invalid modulus: {}", m),
+                CrtError::NoSolution((a1, m1), (a2, m2)) =>
+                    write!(f, "no x with x = {} (mod {}) and x = {} (mod {})", a1, m1, a2, m2),
+                CrtError::Overflow => write!(f, "combined modulus doesn't fit in 128 bits"),
+            }
+        }
+    }
+
+    impl std::error::Error for CrtError {}
+
+    // The smallest non-negative x and the modulus (the lcm of the moduli)
+    // it's unique to, such that x = residue (mod modulus) for every pair.
+    // Moduli needn't be coprime.
+    pub fn crt(congruences: &[(i128, i128)]) -> Result<(i128, i128), CrtError> {
+        // keep some headroom so that doubling in mul_mod can't overflow
+        const MAX_MODULUS: i128 = 1 << 125;
+        congruences.iter().try_fold((0, 1), |(a1, m1), &(a2, m2)| {
+            if m2 <= 0 || m2 > MAX_MODULUS {
+                return Err(CrtError::InvalidModulus(m2));
+            }
+            let a2 = a2.rem_euclid(m2);
+            let (g, _, _) = extended_gcd(m1, m2);
+            let diff = (a2 - a1).rem_euclid(m2);
+            if diff % g != 0 {
+                return Err(CrtError::NoSolution((a1, m1), (a2, m2)));
+            }
+            let lcm = (m1 / g).checked_mul(m2)
+                .filter(|&lcm| lcm <= MAX_MODULUS)
+                .ok_or(CrtError::Overflow)?;
+            // x = a1 + m1 * k where m1 * k = a2 - a1 (mod m2)
+            let m = m2 / g;
+            let inverse = mod_inverse(m1 / g, m).unwrap();
+            let k = mul_mod((diff / g) % m, inverse, m);
+            Ok(((a1 + mul_mod(m1, k, lcm)) % lcm, lcm))
+        })
+    }
+}
 
 fn parse<R>(reader: R) -> Result<(usize, Vec<Option<usize>>), ReadError>
 where R: BufRead {
@@ -16,73 +119,167 @@ where R: BufRead {
         .ok_or_else(|| ReadError::ParseError(1, "Unable to read line".to_string()))?
         .map_err(|_| ReadError::ParseError(1, "Unable to read line".to_string()))?;
 
-    let busses = busses.split(",").map(|id| id.parse().ok()).collect();
+    // anything that isn't a number is out of service, but a bus can't run
+    // every 0 minutes
+    let busses = busses.split(",")
+        .map(|id| match id.parse::<usize>() {
+            Ok(0) => Err(ReadError::ParseError(2, busses.clone())),
+            Ok(id) => Ok(Some(id)),
+            Err(_) => Ok(None),
+        })
+        .collect::<Result<Vec<Option<usize>>, ReadError>>()?;
 
     Ok((time, busses))
 }
 
-fn part1(time: usize, busses: &[Option<usize>]) -> usize {
-    busses
-        .iter()
-        .filter_map(|b| *b)
-        .fold(None, |cur, bus| {
-            let wait = ((time + bus) - (time % bus)) - time;
-            if let Some((_, cur_wait)) = cur {
-                if cur_wait > wait {
-                    Some((bus, wait))
-                } else {
-                    cur
-                }
+#[derive(Debug, PartialEq)]
+enum TimetableError {
+    UnknownBus(usize),
+    Crt(CrtError),
+}
+
+impl fmt::Display for TimetableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimetableError::UnknownBus(bus) => write!(f, "bus {} isn't in the timetable", bus),
+            TimetableError::Crt(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TimetableError {}
+
+impl From<CrtError> for TimetableError {
+    fn from(e: CrtError) -> TimetableError {
+        TimetableError::Crt(e)
+    }
+}
+
+// Buses depart at time 0 and then every `period` (the bus id) thereafter.
+#[derive(Debug, PartialEq)]
+struct Alignment {
+    // the first time, from 0, the buses line up
+    first: i128,
+    period: i128,
+}
+
+impl Alignment {
+    // the first alignment at or after `time`
+    fn next_after(&self, time: i128) -> i128 {
+        if time <= self.first {
+            self.first
+        } else {
+            self.first + (time - self.first + self.period - 1) / self.period * self.period
+        }
+    }
+}
+
+struct Timetable {
+    busses: Vec<Option<usize>>,
+}
+
+impl Timetable {
+    fn new(busses: Vec<Option<usize>>) -> Timetable {
+        Timetable { busses }
+    }
+
+    fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.busses.iter().filter_map(|&b| b)
+    }
+
+    // every departure of `bus` at or after `time`
+    fn departures(bus: usize, time: usize) -> impl Iterator<Item = usize> {
+        let first = time.div_ceil(bus) * bus;
+        (first..).step_by(bus)
+    }
+
+    // the next `n` departures of each bus at or after `time`
+    fn next_departures(&self, time: usize, n: usize) -> Vec<(usize, Vec<usize>)> {
+        self.ids()
+            .map(|bus| (bus, Timetable::departures(bus, time).take(n).collect()))
+            .collect()
+    }
+
+    // (bus, departure) of the first bus to leave at or after `time`
+    fn earliest(&self, time: usize) -> Option<(usize, usize)> {
+        self.ids()
+            .filter_map(|bus| Timetable::departures(bus, time).next().map(|t| (bus, t)))
+            .min_by_key(|&(_, t)| t)
+    }
+
+    // when each (bus, offset) pair has the bus leaving `offset` minutes after
+    // the aligned time
+    fn alignment(&self, offsets: &[(usize, i128)]) -> Result<Alignment, TimetableError> {
+        let congruences = offsets.iter()
+            .map(|&(bus, offset)| if self.ids().any(|b| b == bus) {
+                Ok((-offset, bus as i128))
             } else {
-                Some((bus, wait))
-            }
-        })
-        .map(|(bus, wait)| bus * wait)
+                Err(TimetableError::UnknownBus(bus))
+            })
+            .collect::<Result<Vec<(i128, i128)>, TimetableError>>()?;
+        let (first, period) = crt(&congruences)?;
+        Ok(Alignment { first, period })
+    }
+
+    // the alignment asked for by the puzzle: each bus leaving as many
+    // minutes after the aligned time as its position in the list
+    fn schedule_alignment(&self) -> Result<Alignment, TimetableError> {
+        let offsets: Vec<(usize, i128)> = self.busses.iter().enumerate()
+            .filter_map(|(i, bus)| bus.map(|b| (b, i as i128)))
+            .collect();
+        self.alignment(&offsets)
+    }
+}
+
+fn part1(time: usize, timetable: &Timetable) -> usize {
+    timetable.earliest(time)
+        .map(|(bus, departure)| bus * (departure - time))
         .unwrap_or(0)
 }
 
-fn part2(busses: &[Option<usize>]) -> usize {
-    busses.iter().enumerate()
-        .filter_map(|(i, bus)| bus.map(|b| (i, b)))
-        .fold(None, |prev, (i, b)| {
-            if let Some((pi, pb)) = prev {
-                let (step, step_offset, check, check_offset) = if pb > b { (pb, pi, b, i) } else { (b, i, pb, pi) };
-                let (m, t1) = (1..)
-                    .find_map(|m| {
-                        let target = (m * step) - step_offset;
-                        if (target + check_offset) % check == 0 {
-                            Some((m, target))
-                        } else {
-                            None
-                        }
-                    })
-                    .unwrap();
-                let t2 = (m + 1..)
-                    .find_map(|m| {
-                        let target = (m * step) - step_offset;
-                        if (target + check_offset) % check == 0 {
-                            Some(target - t1)
-                        } else {
-                            None
-                        }
-                    })
-                    .unwrap();
-                Some((t2 - t1, t2))
-            } else {
-                Some((i, b))
-            }
-        })
-        .map(|(o, t)| t - o)
-        .unwrap()
+fn part2(timetable: &Timetable) -> Result<i128, TimetableError> {
+    timetable.schedule_alignment().map(|alignment| alignment.first)
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let f = fs::File::open("day_13_input.txt")?;
     let (time, busses) = parse(BufReader::new(f))?;
-    let result = part1(time, &busses);
-    println!("part1: {}", result);
-    let result = part2(&busses);
-    println!("part2: {}", result);
+    let timetable = Timetable::new(busses);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        Some("next") => {
+            // e.g. `cargo run --bin day_13 -- next 1001612 3`
+            let time = args.get(1).map(|t| t.parse()).transpose()?.unwrap_or(time);
+            let n = args.get(2).map(|n| n.parse()).transpose()?.unwrap_or(5);
+            for (bus, departures) in timetable.next_departures(time, n) {
+                println!("{}: {:?}", bus, departures);
+            }
+        },
+        Some("align") => {
+            // e.g. `cargo run --bin day_13 -- align 19:0 41:9 after 1000000`
+            let split = args.iter().position(|a| a == "after").unwrap_or(args.len());
+            let offsets = args[1..split].iter()
+                .map(|pair| {
+                    let mut parts = pair.splitn(2, ':');
+                    let bus = parts.next()?.parse::<usize>().ok()?;
+                    let offset = parts.next().map(|o| o.parse::<i128>().ok()).unwrap_or(Some(0))?;
+                    Some((bus, offset))
+                })
+                .collect::<Option<Vec<(usize, i128)>>>()
+                .ok_or("expected bus:offset pairs")?;
+            let after = args.get(split + 1).map(|t| t.parse()).transpose()?.unwrap_or(0);
+            let alignment = timetable.alignment(&offsets)?;
+            println!("next alignment: {}", alignment.next_after(after));
+            println!("repeats every: {}", alignment.period);
+        },
+        Some(mode) => return Err(format!("unknown mode: {}", mode).into()),
+        None => {
+            let result = part1(time, &timetable);
+            println!("part1: {}", result);
+            let result = part2(&timetable)?;
+            println!("part2: {}", result);
+        },
+    }
     Ok(())
 }
 
@@ -94,24 +291,76 @@ mod test {
     fn test_1() -> Result<(), Box<dyn std::error::Error>> {
         let input = "939\n7,13,x,x,59,x,31,19";
         let (time, busses) = parse(input.as_bytes())?;
-        assert_eq!(part1(time, &busses), 295);
-        assert_eq!(part2(&busses), 1068781);
+        assert_eq!(part1(time, &Timetable::new(busses.clone())), 295);
+        assert_eq!(part2(&Timetable::new(busses))?, 1068781);
         let input = "0\n17,x,13,19";
         let (_, busses) = parse(input.as_bytes())?;
-        assert_eq!(part2(&busses), 3417);
+        assert_eq!(part2(&Timetable::new(busses))?, 3417);
         let input = "0\n67,7,59,61";
         let (_, busses) = parse(input.as_bytes())?;
-        assert_eq!(part2(&busses), 754018);
+        assert_eq!(part2(&Timetable::new(busses))?, 754018);
         let input = "0\n67,x,7,59,61";
         let (_, busses) = parse(input.as_bytes())?;
-        assert_eq!(part2(&busses), 779210);
+        assert_eq!(part2(&Timetable::new(busses))?, 779210);
         let input = "0\n67,7,x,59,61";
         let (_, busses) = parse(input.as_bytes())?;
-        assert_eq!(part2(&busses), 1261476);
+        assert_eq!(part2(&Timetable::new(busses))?, 1261476);
         let input = "0\n1789,37,47,1889";
         let (_, busses) = parse(input.as_bytes())?;
-        assert_eq!(part2(&busses), 1202161486);
+        assert_eq!(part2(&Timetable::new(busses))?, 1202161486);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_theory() {
+        use number_theory::*;
+
+        assert_eq!(extended_gcd(240, 46), (2, -9, 47));
+        assert_eq!(extended_gcd(-4, 6), (2, 1, 1));
+        assert_eq!(mod_inverse(3, 11), Some(4));
+        assert_eq!(mod_inverse(-3, 11), Some(7));
+        assert_eq!(mod_inverse(6, 9), None);
+        assert_eq!(mul_mod((1 << 100) + 1, (1 << 100) + 3, (1 << 101) + 1), 1_901_475_900_342_344_102_245_054_808_066);
+
+        assert_eq!(crt(&[]), Ok((0, 1)));
+        // 6 and 10 share a factor of 2, so the answer is only unique mod 30
+        assert_eq!(crt(&[(2, 6), (8, 10)]), Ok((8, 30)));
+        assert_eq!(crt(&[(1, 4), (2, 6)]), Err(CrtError::NoSolution((1, 4), (2, 6))));
+        assert_eq!(crt(&[(1, 0)]), Err(CrtError::InvalidModulus(0)));
+
+        // well past 64 bits
+        let moduli = [1_000_000_007, 998_244_353, 1_000_000_009, 2_147_483_647];
+        let (x, modulus) = crt(&moduli.iter().map(|&m| (-5, m)).collect::<Vec<_>>()).unwrap();
+        assert_eq!(modulus, moduli.iter().product());
+        assert_eq!(x, modulus - 5);
+        assert_eq!(crt(&[(0, (1 << 100) - 1), (0, 1 << 100)]), Err(CrtError::Overflow));
+    }
+
+    #[test]
+    fn test_timetable() -> Result<(), Box<dyn std::error::Error>> {
+        let (_, busses) = parse("939\n7,13,x,x,59,x,31,19".as_bytes())?;
+        assert!(parse("939\n7,0,x".as_bytes()).is_err());
+        let timetable = Timetable::new(busses);
+        assert_eq!(timetable.next_departures(939, 2)[..3], [
+            (7, vec![945, 952]),
+            (13, vec![949, 962]),
+            (59, vec![944, 1003]),
+        ]);
+        assert_eq!(timetable.earliest(939), Some((59, 944)));
+        assert_eq!(timetable.earliest(945), Some((7, 945)));
+
+        let alignment = timetable.schedule_alignment()?;
+        assert_eq!(alignment, Alignment { first: 1068781, period: 7 * 13 * 59 * 31 * 19 });
+        assert_eq!(alignment.next_after(1068782), 1068781 + alignment.period);
+        assert_eq!(alignment.next_after(0), 1068781);
 
+        // 13 leaving with 7 a minute later first happens at 13...
+        let alignment = timetable.alignment(&[(13, 0), (7, 1)])?;
+        assert_eq!(alignment, Alignment { first: 13, period: 91 });
+        // ...and negative offsets leave earlier, so 13 leaves at 13 here too
+        assert_eq!(timetable.alignment(&[(7, 0), (13, -1)])?.first, 14);
+        assert_eq!(timetable.alignment(&[(11, 0)]), Err(TimetableError::UnknownBus(11)));
         Ok(())
     }
 }
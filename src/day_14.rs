@@ -1,127 +1,289 @@
-use std::{fs, io::{BufRead, BufReader}};
-use std::collections::HashMap;
+use std::{env, fmt, fs, io::{BufRead, BufReader}};
+use std::collections::BTreeMap;
+use adventofcode2020::ReadError;
 
+const MAX_WIDTH: u32 = 128;
 
-fn part1<R>(reader: R) -> u64
-where R: BufRead {
-    let mut m1 = 0;
-    let mut m2 = 0;
-    let mut memory = HashMap::new();
-    reader.lines().enumerate()
-        .for_each(|(i, line)| {
-            let line = line.unwrap_or_else(|_| panic!("Unable to read line: {}", i + 1));
-            if line.starts_with("mask = ") {
-                let (m1x, m2x) = line[7..].chars().fold((0, 0), |(m1, m2), c| {
-                    let (v1, v2) = match c {
-                        '1' => (1, 1),
-                        '0' => (0, 0),
-                        'X' => (1, 0),
-                        _ => panic!("invalid value in mask: {}", c)
-                    };
-                    ((m1 << 1) + v1, (m2 << 1) + v2)
-                });
-                m1 = m1x;
-                m2 = m2x;
-            } else {
-                let addr = line[4..line.find("]").unwrap()].parse::<usize>().unwrap();
-                let val = line[line.find("=").unwrap() + 2..].parse::<u64>().unwrap();
-                memory.insert(addr, (val & m1) | m2);
-            }
-        });
-    memory.values().sum()
+// Bits set in `ones` are 1s in the mask and bits set in `floating` are Xs;
+// the rest of the word is 0s.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Mask {
+    ones: u128,
+    floating: u128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Statement {
+    Mask(Mask),
+    Write { address: u128, value: u128 },
+}
+
+fn parse_mask(mask: &str, width: u32) -> Result<Mask, String> {
+    if mask.len() != width as usize {
+        return Err(format!("mask should be {} bits long", width));
+    }
+    mask.chars().try_fold(Mask { ones: 0, floating: 0 }, |m, c| {
+        let (one, floating) = match c {
+            '1' => (1, 0),
+            '0' => (0, 0),
+            'X' => (0, 1),
+            _ => return Err(format!("invalid value in mask: {}", c)),
+        };
+        Ok(Mask { ones: (m.ones << 1) | one, floating: (m.floating << 1) | floating })
+    })
+}
+
+fn parse_word(word: &str, width: u32) -> Result<u128, String> {
+    let n = word.trim().parse::<u128>().map_err(|_| format!("invalid number: {}", word))?;
+    if width < MAX_WIDTH && n >> width != 0 {
+        return Err(format!("{} doesn't fit in {} bits", n, width));
+    }
+    Ok(n)
+}
+
+fn parse_statement(line: &str, width: u32) -> Result<Statement, String> {
+    let (target, argument) = line.split_once('=').ok_or("expected an assignment")?;
+    let target = target.trim();
+    let argument = argument.trim();
+    if target == "mask" {
+        return parse_mask(argument, width).map(Statement::Mask);
+    }
+    let address = target.strip_prefix("mem[")
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| format!("unknown target: {}", target))?;
+    Ok(Statement::Write { address: parse_word(address, width)?, value: parse_word(argument, width)? })
 }
 
 #[derive(Debug)]
-enum Bit {
-    Zero,
-    One,
-    Floating
-}
-
-fn apply_floating_mask(
-    mask_bits: &[Bit],
-    base_addr: u64,
-    cur_addr: u64,
-    memory: &mut HashMap<u64, u64>,
-    value: &u64
-) {
-    if mask_bits.len() == 0 {
-        memory.insert(cur_addr, *value);
-    } else {
-        match &mask_bits[0] {
-            Bit::Zero => {
-                let bit = base_addr & 1;
-                apply_floating_mask(
-                    &mask_bits[1..],
-                    base_addr >> 1,
-                    (cur_addr << 1) + bit,
-                    memory,
-                    value
-                );
-            },
-            Bit::One => {
-                apply_floating_mask(
-                    &mask_bits[1..],
-                    base_addr >> 1,
-                    (cur_addr << 1) + 1,
-                    memory,
-                    value
-                );
-            },
-            Bit::Floating => {
-                apply_floating_mask(
-                    &mask_bits[1..],
-                    base_addr >> 1,
-                    (cur_addr << 1) + 0,
-                    memory,
-                    value
-                );
-                apply_floating_mask(
-                    &mask_bits[1..],
-                    base_addr >> 1,
-                    (cur_addr << 1) + 1,
-                    memory,
-                    value
-                );
-            }
+enum ProgramError {
+    UnsupportedWidth(u32),
+    Read(ReadError),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::UnsupportedWidth(width) =>
+                write!(f, "unsupported word width: {} (expected 1 to {})", width, MAX_WIDTH),
+            ProgramError::Read(e) => write!(f, "{}", e),
         }
     }
 }
 
-fn part2<R>(reader: R) -> u64
-where R: BufRead {
-    let mut mask = vec![];
-    let mut memory = HashMap::new();
+impl std::error::Error for ProgramError {}
+
+impl From<ReadError> for ProgramError {
+    fn from(e: ReadError) -> ProgramError {
+        ProgramError::Read(e)
+    }
+}
+
+// a program over `width` bit words, which has to set a mask before writing
+fn parse_program<R: BufRead>(reader: R, width: u32) -> Result<Vec<Statement>, ProgramError> {
+    if width == 0 || width > MAX_WIDTH {
+        return Err(ProgramError::UnsupportedWidth(width));
+    }
+    let mut seen_mask = false;
     reader.lines().enumerate()
-        .for_each(|(i, line)| {
-            let line = line.unwrap_or_else(|_| panic!("Unable to read line: {}", i + 1));
-            if line.starts_with("mask = ") {
-                mask = line[7..].chars().map(|c| {
-                    match c {
-                        '1' => Bit::One,
-                        '0' => Bit::Zero,
-                        'X' => Bit::Floating,
-                        _ => panic!("invalid value in mask: {}", c)
-                    }
-                }).rev().collect();
+        .filter(|(_, line)| line.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+        .map(|(line_no, line)| {
+            let line_no = line_no + 1;
+            let line = line.map_err(|e| ReadError::IoError(Some(line_no), e))?;
+            let statement = parse_statement(&line, width)
+                .map_err(|e| ReadError::ParseError(line_no, format!("{}: {}", e, line)))?;
+            match statement {
+                Statement::Mask(_) => seen_mask = true,
+                Statement::Write { .. } if !seen_mask =>
+                    return Err(ReadError::ParseError(line_no, format!("write before any mask: {}", line))),
+                _ => {},
+            }
+            Ok(statement)
+        })
+        .collect::<Result<Vec<Statement>, ReadError>>()
+        .map_err(ProgramError::Read)
+}
+
+// A set of addresses: the bits set in `floating` take either value, and
+// every other bit is as in `fixed` (which has no floating bits set).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct AddressPattern {
+    fixed: u128,
+    floating: u128,
+}
+
+impl AddressPattern {
+    fn new(mask_ones: u128, mask_floating: u128, address: u128) -> AddressPattern {
+        AddressPattern {
+            fixed: (address | mask_ones) & !mask_floating,
+            floating: mask_floating,
+        }
+    }
+
+    // None if there are 2^128 of them
+    fn len(&self) -> Option<u128> {
+        1u128.checked_shl(self.floating.count_ones())
+    }
+
+    fn intersects(&self, other: &AddressPattern) -> bool {
+        (self.fixed ^ other.fixed) & !(self.floating | other.floating) == 0
+    }
+
+    // the addresses in `self` but not in `other`, as disjoint patterns. Each
+    // bit that floats here but is fixed in `other` splits off the half that
+    // disagrees with `other`, narrowing down to the overlap, which is dropped.
+    fn subtract(&self, other: &AddressPattern) -> Vec<AddressPattern> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+        let mut pieces = vec![];
+        let mut rest = *self;
+        let mut splitting = self.floating & !other.floating;
+        while splitting != 0 {
+            let bit = splitting & splitting.wrapping_neg();
+            splitting &= !bit;
+            rest.floating &= !bit;
+            pieces.push(AddressPattern { fixed: rest.fixed | (!other.fixed & bit), floating: rest.floating });
+            rest.fixed |= other.fixed & bit;
+        }
+        pieces
+    }
+
+    // the address in decimal, or as a mask style pattern if it floats
+    fn render(&self, width: u32) -> String {
+        if self.floating == 0 {
+            return self.fixed.to_string();
+        }
+        (0..width).rev().map(|bit| {
+            if self.floating >> bit & 1 == 1 {
+                'X'
+            } else if self.fixed >> bit & 1 == 1 {
+                '1'
             } else {
-                let addr = line[4..line.find("]").unwrap()].parse::<u64>().unwrap();
-                let val = line[line.find("=").unwrap() + 2..].parse::<u64>().unwrap();
-                apply_floating_mask(&mask, addr, 0, &mut memory, &val);
+                '0'
             }
-        });
-    memory.values().sum()
+        }).collect()
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let file = fs::File::open("day_14_input.txt")?;
-    let result = part1(BufReader::new(file));
-    println!("{}", result);
+// How writes are decoded into memory.
+trait Decoder {
+    fn write(&mut self, mask: &Mask, address: u128, value: u128);
 
-    let file = fs::File::open("day_14_input.txt")?;
-    let result = part2(BufReader::new(file));
-    println!("{}", result);
+    // every address (or set of addresses) written to and its final value,
+    // in address order
+    fn contents(&self) -> Vec<(AddressPattern, u128)>;
 
+    // None if the sum doesn't fit in 128 bits
+    fn sum(&self) -> Option<u128> {
+        self.contents().iter().try_fold(0u128, |sum, (pattern, value)| {
+            pattern.len()?.checked_mul(*value)?.checked_add(sum)
+        })
+    }
+}
+
+// Version 1: the mask applies to the value.
+#[derive(Default)]
+struct ValueDecoder {
+    memory: BTreeMap<u128, u128>,
+}
+
+impl Decoder for ValueDecoder {
+    fn write(&mut self, mask: &Mask, address: u128, value: u128) {
+        self.memory.insert(address, (value & mask.floating) | mask.ones);
+    }
+
+    fn contents(&self) -> Vec<(AddressPattern, u128)> {
+        self.memory.iter()
+            .map(|(&address, &value)| (AddressPattern { fixed: address, floating: 0 }, value))
+            .collect()
+    }
+}
+
+// Version 2: the mask applies to the address, with each X standing for both
+// values. Writes are kept as disjoint patterns so that a write to 2^k
+// addresses costs nothing like 2^k.
+#[derive(Default)]
+struct AddressDecoder {
+    writes: Vec<(AddressPattern, u128)>,
+}
+
+impl Decoder for AddressDecoder {
+    fn write(&mut self, mask: &Mask, address: u128, value: u128) {
+        let pattern = AddressPattern::new(mask.ones, mask.floating, address);
+        self.writes = self.writes.iter()
+            .flat_map(|(p, v)| p.subtract(&pattern).into_iter().map(move |q| (q, *v)))
+            .collect();
+        self.writes.push((pattern, value));
+    }
+
+    fn contents(&self) -> Vec<(AddressPattern, u128)> {
+        let mut contents = self.writes.clone();
+        contents.sort();
+        contents
+    }
+}
+
+fn run<D: Decoder>(program: &[Statement], mut decoder: D) -> D {
+    let mut mask = Mask { ones: 0, floating: 0 };
+    for statement in program {
+        match *statement {
+            Statement::Mask(m) => mask = m,
+            Statement::Write { address, value } => decoder.write(&mask, address, value),
+        }
+    }
+    decoder
+}
+
+// memory as a two column table
+fn dump<D: Decoder>(decoder: &D, width: u32) -> String {
+    let rows: Vec<(String, String)> = decoder.contents().iter()
+        .map(|(pattern, value)| (pattern.render(width), value.to_string()))
+        .collect();
+    let address_width = rows.iter().map(|(a, _)| a.len()).max().unwrap_or(0).max("address".len());
+    let mut table = format!("{:>w$}  value\n", "address", w = address_width);
+    for (address, value) in rows {
+        table += &format!("{:>w$}  {}\n", address, value, w = address_width);
+    }
+    table
+}
+
+fn part1(program: &[Statement]) -> Option<u128> {
+    run(program, ValueDecoder::default()).sum()
+}
+
+fn part2(program: &[Statement]) -> Option<u128> {
+    run(program, AddressDecoder::default()).sum()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // e.g. `cargo run --bin day_14 -- dump v2 --width 40`
+    let width = match args.iter().position(|a| a == "--width") {
+        Some(idx) => {
+            let width = args.get(idx + 1)
+                .and_then(|w| w.parse::<u32>().ok())
+                .ok_or("expected a number of bits after --width")?;
+            args.drain(idx..=idx + 1);
+            width
+        },
+        None => 36,
+    };
+    let file = fs::File::open("day_14_input.txt")?;
+    let program = parse_program(BufReader::new(file), width)?;
+    match args.first().map(|a| a.as_str()) {
+        Some("dump") => match args.get(1).map(|v| v.as_str()) {
+            Some("v1") => print!("{}", dump(&run(&program, ValueDecoder::default()), width)),
+            Some("v2") => print!("{}", dump(&run(&program, AddressDecoder::default()), width)),
+            _ => return Err("expected a decoder version: v1 or v2".into()),
+        },
+        Some(mode) => return Err(format!("unknown mode: {}", mode).into()),
+        None => {
+            let result = part1(&program).ok_or("sum doesn't fit in 128 bits")?;
+            println!("{}", result);
+            let result = part2(&program).ok_or("sum doesn't fit in 128 bits")?;
+            println!("{}", result);
+        },
+    }
     Ok(())
 }
 
@@ -136,7 +298,7 @@ mem[8] = 11
 mem[7] = 101
 mem[8] = 0
 "#;
-        assert_eq!(part1(input.as_bytes()), 165);
+        assert_eq!(part1(&parse_program(input.as_bytes(), 36)?), Some(165));
         Ok(())
     }
 
@@ -147,8 +309,61 @@ mem[42] = 100
 mask = 00000000000000000000000000000000X0XX
 mem[26] = 1
 "#;
-        assert_eq!(part2(input.as_bytes()), 208);
+        assert_eq!(part2(&parse_program(input.as_bytes(), 36)?), Some(208));
         Ok(())
     }
 
+    #[test]
+    fn test_floating_memory() -> Result<(), Box<dyn std::error::Error>> {
+        let all = AddressPattern { fixed: 0, floating: 0b111 };
+        let corner = AddressPattern { fixed: 0b101, floating: 0 };
+        let pieces = all.subtract(&corner);
+        assert_eq!(pieces.iter().map(|p| p.len().unwrap()).sum::<u128>(), 7);
+        assert!(pieces.iter().all(|p| !p.intersects(&corner)));
+        assert!(pieces.iter().enumerate().all(|(i, p)| pieces[i + 1..].iter().all(|q| !p.intersects(q))));
+        assert_eq!(corner.subtract(&all), vec![]);
+        assert_eq!(corner.subtract(&AddressPattern { fixed: 0b100, floating: 0 }), vec![corner]);
+
+        // 2^36 addresses, half of them then overwritten
+        let input = r#"mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 1
+mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX0
+mem[0] = 2
+"#;
+        assert_eq!(part2(&parse_program(input.as_bytes(), 36)?), Some(3 << 35));
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_width() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "mask = 1X0X\nmem[3] = 15\nmem[1] = 2\n";
+        let program = parse_program(input.as_bytes(), 4)?;
+        let v1 = run(&program, ValueDecoder::default());
+        assert_eq!(dump(&v1, 4), "address  value\n      1  8\n      3  13\n");
+        let v2 = run(&program, AddressDecoder::default());
+        // 0s leave address bits alone, so the writes don't overlap
+        assert_eq!(v2.sum(), Some(4 * 15 + 4 * 2));
+        assert_eq!(dump(&v2, 4), "address  value\n   1X0X  2\n   1X1X  15\n");
+
+        let ones = "1".repeat(128);
+        let input = format!("mask = {}\nmem[{}] = {}\n", "X".repeat(128), u128::MAX, u128::MAX);
+        let program = parse_program(input.as_bytes(), 128)?;
+        assert_eq!(part1(&program), Some(u128::MAX));
+        // 2^128 addresses
+        assert_eq!(part2(&program), None);
+        let input = format!("mask = 0{}\nmem[0] = 1\n", "X".repeat(127));
+        assert_eq!(part2(&parse_program(input.as_bytes(), 128)?), Some(1 << 127));
+        assert!(part1(&parse_program(format!("mask = {}\nmem[0] = 0\n", ones).as_bytes(), 128)?).is_some());
+
+        assert!(parse_program("mem[0] = 1\n".as_bytes(), 4).is_err());
+        assert!(parse_program("mask = 1X0\n".as_bytes(), 4).is_err());
+        assert!(parse_program("mask = 1X0X\nmem[16] = 1\n".as_bytes(), 4).is_err());
+        assert!(parse_program("mask = 1X0X\nmem[1 = 1\n".as_bytes(), 4).is_err());
+        assert!(matches!(
+            parse_program("mask = 1X0X\nmem[1] = 1\n".as_bytes(), 129),
+            Err(ProgramError::UnsupportedWidth(129))
+        ));
+        assert!(matches!(parse_program("mask = 1X0\n".as_bytes(), 4), Err(ProgramError::Read(_))));
+        Ok(())
+    }
 }
@@ -1,4 +1,14 @@
+use std::{env, fmt::Write, fs, time::Instant};
 use std::collections::HashMap;
+use adventofcode2020::ReadError;
+
+const SNAPSHOT_HEADER: &str = "van eck snapshot v1";
+// values below this are tracked in the dense table when the number of turns
+// isn't known up front
+const DEFAULT_DENSE_LIMIT: usize = 1 << 20;
+// the largest dense table a restored snapshot gets, enough for part 2, with
+// anything larger in the file treated as sparse
+const MAX_RESTORED_DENSE_LIMIT: usize = 1 << 25;
 
 fn solve(starting: &[usize], limit: usize) -> usize {
     let mut map = starting.iter().take(starting.len() - 1).enumerate()
@@ -10,13 +20,190 @@ fn solve(starting: &[usize], limit: usize) -> usize {
         })
 }
 
-fn main() {
+// The Van Eck style memory game as an iterator over the numbers spoken,
+// starting numbers first. The turn each number was last spoken on is kept in
+// a dense table for values below `dense.len()` and a map for the rest. Turns
+// are u32s (0 meaning never spoken), so the game ends after u32::MAX turns.
+struct VanEck {
+    starting: Vec<u32>,
+    dense: Vec<u32>,
+    sparse: HashMap<u32, u32>,
+    turn: u32,
+    last: u32,
+}
+
+impl VanEck {
+    fn new(starting: &[u32]) -> VanEck {
+        VanEck::with_dense_limit(starting, DEFAULT_DENSE_LIMIT)
+    }
+
+    // no number spoken within `turns` turns can be `turns` or more, so this
+    // never needs the sparse map
+    fn for_turns(starting: &[u32], turns: u32) -> VanEck {
+        let largest_start = starting.iter().max().map(|&n| n as usize + 1).unwrap_or(0);
+        VanEck::with_dense_limit(starting, largest_start.max(turns as usize))
+    }
+
+    fn with_dense_limit(starting: &[u32], dense_limit: usize) -> VanEck {
+        VanEck {
+            starting: starting.to_vec(),
+            dense: vec![0; dense_limit],
+            sparse: HashMap::new(),
+            turn: 0,
+            last: 0,
+        }
+    }
+
+    // records `value` as spoken on `turn`, returning when it was spoken before
+    fn record(&mut self, value: u32, turn: u32) -> u32 {
+        match self.dense.get_mut(value as usize) {
+            Some(seen) => std::mem::replace(seen, turn),
+            None => self.sparse.insert(value, turn).unwrap_or(0),
+        }
+    }
+
+    // the number spoken on turn `n` (counting from 1), or None if that turn
+    // has already gone
+    fn term(&mut self, n: u32) -> Option<u32> {
+        if n == 0 || n < self.turn {
+            return None;
+        }
+        while self.turn < n {
+            self.next()?;
+        }
+        Some(self.last)
+    }
+
+    fn snapshot(&self) -> String {
+        let join = |vals: &[u32]| vals.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(" ");
+        let mut out = String::new();
+        writeln!(out, "{}", SNAPSHOT_HEADER).unwrap();
+        writeln!(out, "starting {}", join(&self.starting)).unwrap();
+        writeln!(out, "dense-limit {}", self.dense.len()).unwrap();
+        writeln!(out, "turn {}", self.turn).unwrap();
+        writeln!(out, "last {}", self.last).unwrap();
+        let dense = self.dense.iter().enumerate()
+            .filter(|(_, &turn)| turn != 0)
+            .map(|(value, &turn)| (value as u32, turn));
+        let mut sparse: Vec<(u32, u32)> = self.sparse.iter().map(|(&v, &t)| (v, t)).collect();
+        sparse.sort_unstable();
+        for (value, turn) in dense.chain(sparse) {
+            writeln!(out, "seen {} {}", value, turn).unwrap();
+        }
+        out
+    }
+
+    fn restore(snapshot: &str) -> Result<VanEck, ReadError> {
+        let mut lines = snapshot.lines().enumerate().map(|(line_no, line)| (line_no + 1, line));
+        match lines.next() {
+            Some((_, SNAPSHOT_HEADER)) => {},
+            Some((line_no, line)) => return Err(ReadError::ParseError(line_no, line.to_string())),
+            None => return Err(ReadError::ParseError(0, String::new())),
+        }
+        let mut starting = vec![];
+        let mut dense_limit = 0;
+        let mut turn = 0;
+        let mut last = 0;
+        // (line number, line, value, turn), checked once the turn is known
+        let mut seen = vec![];
+        for (line_no, line) in lines {
+            let err = || ReadError::ParseError(line_no, line.to_string());
+            let (key, vals) = line.split_once(' ').unwrap_or((line, ""));
+            let numbers = vals.split_whitespace()
+                .map(|v| v.parse::<u32>().map_err(|_| err()))
+                .collect::<Result<Vec<u32>, ReadError>>()?;
+            match (key, numbers.as_slice()) {
+                ("starting", _) => starting = numbers,
+                ("dense-limit", &[limit]) => dense_limit = limit as usize,
+                ("turn", &[t]) => turn = t,
+                ("last", &[l]) => last = l,
+                ("seen", &[value, t]) => seen.push((line_no, line, value, t)),
+                _ => return Err(err()),
+            }
+        }
+        let mut game = VanEck::with_dense_limit(&starting, dense_limit.min(MAX_RESTORED_DENSE_LIMIT));
+        game.turn = turn;
+        game.last = last;
+        for (line_no, line, value, t) in seen {
+            if t == 0 || t > turn {
+                return Err(ReadError::ParseError(line_no, line.to_string()));
+            }
+            game.record(value, t);
+        }
+        Ok(game)
+    }
+}
+
+impl Iterator for VanEck {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.turn == u32::MAX {
+            return None;
+        }
+        let previous = if self.turn > 0 { self.record(self.last, self.turn) } else { 0 };
+        let next = match self.starting.get(self.turn as usize) {
+            Some(&n) => n,
+            None if previous == 0 => 0,
+            None => self.turn - previous,
+        };
+        self.turn += 1;
+        self.last = next;
+        Some(next)
+    }
+}
+
+fn part1(starting: &[u32]) -> u32 {
+    VanEck::for_turns(starting, 2020).term(2020).unwrap()
+}
+
+fn part2(starting: &[u32]) -> u32 {
+    VanEck::for_turns(starting, 30000000).term(30000000).unwrap()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = [2, 1, 10, 11, 0, 6];
-    let result = solve(&input, 2020);
-    println!("part1: {}", result);
-    let n = std::time::Instant::now();
-    let result = solve(&input, 30000000);
-    println!("part2: {} ({:?})", result, n.elapsed());
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [mode] if mode == "compare" => {
+            let turns = 30000000;
+            let n = Instant::now();
+            let result = solve(&input.iter().map(|&x| x as usize).collect::<Vec<usize>>(), turns);
+            let elapsed = n.elapsed();
+            println!("hash map: {} in {:?} ({:.1}M turns/s)", result, elapsed, turns as f64 / elapsed.as_secs_f64() / 1e6);
+            for (name, mut game) in [
+                ("dense", VanEck::for_turns(&input, turns as u32)),
+                ("dense + sparse", VanEck::new(&input)),
+            ] {
+                let n = Instant::now();
+                let result = game.term(turns as u32).unwrap();
+                let elapsed = n.elapsed();
+                println!("{}: {} in {:?} ({:.1}M turns/s)", name, result, elapsed, turns as f64 / elapsed.as_secs_f64() / 1e6);
+            }
+        },
+        // e.g. `cargo run --bin day_15 -- save game.txt 1000000` then
+        // `cargo run --bin day_15 -- resume game.txt 30000000`
+        [mode, path, turn] if mode == "save" => {
+            let mut game = VanEck::new(&input);
+            let term = game.term(turn.parse()?).ok_or("invalid turn")?;
+            fs::write(path, game.snapshot())?;
+            println!("turn {}: {}", turn, term);
+        },
+        [mode, path, turn] if mode == "resume" => {
+            let mut game = VanEck::restore(&fs::read_to_string(path)?)?;
+            let term = game.term(turn.parse()?).ok_or("turn already passed")?;
+            println!("turn {}: {}", turn, term);
+        },
+        [] => {
+            let result = part1(&input);
+            println!("part1: {}", result);
+            let n = Instant::now();
+            let result = part2(&input);
+            println!("part2: {} ({:?})", result, n.elapsed());
+        },
+        _ => return Err("expected compare, save <file> <turn> or resume <file> <turn>".into()),
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -25,12 +212,54 @@ mod test {
 
     #[test]
     fn test_1() {
-        assert_eq!(part1(&[0,3,6], 2020), 436);
-        assert_eq!(part1(&[1,3,2], 2020), 1);
-        assert_eq!(part1(&[2,1,3], 2020), 10);
-        assert_eq!(part1(&[1,2,3], 2020), 27);
-        assert_eq!(part1(&[2,3,1], 2020), 78);
-        assert_eq!(part1(&[3,2,1], 2020), 438);
-        assert_eq!(part1(&[3,1,2], 2020), 1836);
+        assert_eq!(part1(&[0,3,6]), 436);
+        assert_eq!(part1(&[1,3,2]), 1);
+        assert_eq!(part1(&[2,1,3]), 10);
+        assert_eq!(part1(&[1,2,3]), 27);
+        assert_eq!(part1(&[2,3,1]), 78);
+        assert_eq!(part1(&[3,2,1]), 438);
+        assert_eq!(part1(&[3,1,2]), 1836);
+        assert_eq!(solve(&[0,3,6], 2020), 436);
+    }
+
+    #[test]
+    fn test_engine() -> Result<(), Box<dyn std::error::Error>> {
+        let terms: Vec<u32> = VanEck::new(&[0, 3, 6]).take(10).collect();
+        assert_eq!(terms, vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0]);
+
+        // mostly sparse agrees with fully dense and with the hash map version
+        let sparse = VanEck::with_dense_limit(&[2, 1, 10, 11, 0, 6], 4);
+        let dense = VanEck::for_turns(&[2, 1, 10, 11, 0, 6], 100_000);
+        assert!(sparse.zip(dense).take(100_000).all(|(a, b)| a == b));
+        let mut game = VanEck::with_dense_limit(&[2, 1, 10, 11, 0, 6], 16);
+        assert_eq!(game.term(100_000), Some(solve(&[2, 1, 10, 11, 0, 6], 100_000) as u32));
+        assert_eq!(game.term(99_999), None);
+        assert_eq!(game.term(0), None);
+
+        let mut game = VanEck::with_dense_limit(&[0, 3, 6], 100);
+        assert_eq!(game.term(2), Some(3));
+        let snapshot = game.snapshot();
+        assert!(snapshot.starts_with("van eck snapshot v1\nstarting 0 3 6\ndense-limit 100\nturn 2\nlast 3\nseen 0 1\n"));
+        let mut resumed = VanEck::restore(&snapshot)?;
+        assert_eq!(resumed.term(2020), Some(436));
+        // the rest of the game is unaffected by stopping part way through
+        assert_eq!(resumed.next(), VanEck::new(&[0, 3, 6]).nth(2020));
+        assert_eq!(VanEck::restore(&VanEck::new(&[0, 3, 6]).snapshot())?.term(2020), Some(436));
+
+        let mut game = VanEck::with_dense_limit(&[0, 3, 6], 2);
+        game.term(500);
+        assert_eq!(VanEck::restore(&game.snapshot())?.term(2020), Some(436));
+        assert!(VanEck::restore("van eck snapshot v2\n").is_err());
+        assert!(VanEck::restore("van eck snapshot v1\nturn 1 2\n").is_err());
+        assert!(VanEck::restore("van eck snapshot v1\nturn 3\nseen 1 4\n").is_err());
+
+        // fields can come in any order, and an absurd dense limit is capped
+        let mut reordered: Vec<&str> = snapshot.lines().collect();
+        reordered[1..].reverse();
+        let reordered = reordered.join("\n").replace("dense-limit 100", "dense-limit 4294967295");
+        let mut restored = VanEck::restore(&reordered)?;
+        assert_eq!(restored.dense.len(), MAX_RESTORED_DENSE_LIMIT);
+        assert_eq!(restored.term(2020), Some(436));
+        Ok(())
     }
 }